@@ -1,19 +1,21 @@
 #![allow(clippy::type_complexity)]
 
-use std::{collections::HashMap, net::SocketAddr, pin::Pin, rc::Rc};
+use std::{net::SocketAddr, pin::Pin, rc::Rc};
 
 use bytes::Bytes;
 use http_body_util::Full;
 use hyper::{
     body::Incoming,
+    header,
     server::conn::http1,
     service::{service_fn, Service},
     Method, Request, Response, StatusCode,
 };
 use monoio::{io::IntoPollIo, net::TcpListener};
+use monoio_route::MethodLookup;
 use std::future::Future;
 
-type Router<S> = HashMap<Method, monoio_route::Tree<S>>;
+type Router<S> = monoio_route::MethodRouter<S>;
 type BoxFuture<O> = Pin<Box<dyn Future<Output = O>>>;
 type BoxHyperService<Req, Resp, E> = Box<
     dyn Service<Req, Response = Resp, Error = E, Future = BoxFuture<Result<Resp, E>>> + 'static,
@@ -98,25 +100,34 @@ impl Service<Request<Incoming>> for HyperSvc {
     type Future = BoxFuture<Result<Self::Response, Self::Error>>;
 
     fn call(&self, req: Request<Incoming>) -> Self::Future {
-        // find the subrouter for this request method
-        let router = match self.router.get(req.method()) {
-            Some(router) => router,
-            // if there are no routes for this method, respond with 405 Method Not Allowed
-            None => {
+        // find the service for this request method and path
+        let path = req.uri().path().as_bytes();
+        let svc = match self.router.at(req.method().as_str().as_bytes(), path) {
+            MethodLookup::Found(svc, _) => svc,
+            // the path exists with other methods, respond with 405 Method Not Allowed
+            MethodLookup::MethodNotAllowed { allowed } => {
+                let allow = allowed.header_value();
                 return Box::pin(async move {
                     Ok(Response::builder()
                         .status(StatusCode::METHOD_NOT_ALLOWED)
+                        .header(header::ALLOW, allow)
+                        .body(Full::new(Bytes::new()))
+                        .unwrap())
+                });
+            }
+            // answer OPTIONS with the allowed methods
+            MethodLookup::Options { allowed } => {
+                let allow = allowed.header_value();
+                return Box::pin(async move {
+                    Ok(Response::builder()
+                        .status(StatusCode::NO_CONTENT)
+                        .header(header::ALLOW, allow)
                         .body(Full::new(Bytes::new()))
                         .unwrap())
                 });
             }
-        };
-
-        // find the service for this request path
-        let svc = match router.at(req.uri().path().as_bytes()) {
-            Some((svc, _)) => svc,
             // if we there is no matching service, respond with 404 Not Found
-            None => {
+            MethodLookup::NotFound => {
                 return Box::pin(async move {
                     Ok(Response::builder()
                         .status(StatusCode::NOT_FOUND)
@@ -145,27 +156,20 @@ async fn blog(_req: Request<Incoming>) -> hyper::Result<Response<Full<Bytes>>> {
 async fn main() {
     println!("Running http server on 127.0.0.1:3000");
     let mut router = Router::default();
+    let get = Method::GET.as_str().as_bytes();
     // GET / => `index`
     router
-        .entry(Method::GET)
-        .or_default()
-        .insert(b"/", ServiceWrapper(service_fn(index)).boxed())
+        .insert(get, b"/", ServiceWrapper(service_fn(index)).boxed())
         .unwrap();
     // GET /blog => `blog`
     router
-        .entry(Method::GET)
-        .or_default()
-        .insert(b"/aa/bb/cc", ServiceWrapper(service_fn(blog)).boxed())
+        .insert(get, b"/aa/bb/cc", ServiceWrapper(service_fn(blog)).boxed())
         .unwrap();
     router
-        .entry(Method::GET)
-        .or_default()
-        .insert(b"/a/{name}", ServiceWrapper(service_fn(blog)).boxed())
+        .insert(get, b"/a/:name", ServiceWrapper(service_fn(blog)).boxed())
         .unwrap();
     router
-        .entry(Method::GET)
-        .or_default()
-        .insert(b"/a/{*any}", ServiceWrapper(service_fn(blog)).boxed())
+        .insert(get, b"/a/*any", ServiceWrapper(service_fn(blog)).boxed())
         .unwrap();
 
     let _ = serve_http(([127, 0, 0, 1], 3000), HyperSvc::new(router)).await;
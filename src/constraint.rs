@@ -0,0 +1,156 @@
+use std::mem;
+
+#[cfg(feature = "regex")]
+#[derive(Debug, Clone)]
+pub(crate) struct Regex(regex::bytes::Regex);
+
+/// Placeholder which can never be constructed when the `regex` feature is disabled.
+#[cfg(not(feature = "regex"))]
+#[derive(Debug, Clone)]
+pub(crate) enum Regex {}
+
+impl Regex {
+    /// Compile the pattern, which must match the whole param value.
+    #[cfg(feature = "regex")]
+    pub(crate) fn new(pattern: &[u8]) -> Option<Self> {
+        let pattern = std::str::from_utf8(pattern).ok()?;
+        regex::bytes::Regex::new(&format!("^(?:{pattern})$"))
+            .ok()
+            .map(Self)
+    }
+
+    #[cfg(not(feature = "regex"))]
+    #[inline(always)]
+    pub(crate) fn new(_pattern: &[u8]) -> Option<Self> {
+        None
+    }
+
+    /// The pattern as written in the route.
+    #[inline]
+    pub(crate) fn pattern(&self) -> &[u8] {
+        #[cfg(feature = "regex")]
+        {
+            let anchored = self.0.as_str().as_bytes();
+            &anchored[4..anchored.len() - 2]
+        }
+        #[cfg(not(feature = "regex"))]
+        match *self {}
+    }
+
+    #[inline]
+    pub(crate) fn is_match(&self, value: &[u8]) -> bool {
+        #[cfg(feature = "regex")]
+        {
+            self.0.is_match(value)
+        }
+        #[cfg(not(feature = "regex"))]
+        {
+            let _ = value;
+            match *self {}
+        }
+    }
+}
+
+/// Constraint checked against a param value, written as `:name<constraint>`.
+#[derive(Debug, Clone)]
+pub(crate) enum Constraint {
+    /// `u64`: decimal digits fitting in a `u64`.
+    U64,
+    /// `i64`: decimal digits with an optional leading `-`, fitting in an `i64`.
+    I64,
+    /// `uuid`: hyphenated uuid like `67e55044-10b1-426f-9247-bb680e5fe0c8`.
+    Uuid,
+    /// `hex`: one or more hex digits.
+    Hex,
+    /// `alnum`: one or more ASCII letters or digits.
+    Alnum,
+    /// `len(min,max)`: value length in bytes in `min..=max`.
+    Len(usize, usize),
+    /// Anything else is a regex when the `regex` feature is enabled.
+    Regex(Regex),
+}
+
+impl Constraint {
+    pub(crate) fn parse(text: &[u8]) -> Option<Self> {
+        Some(match text {
+            b"u64" => Self::U64,
+            b"i64" => Self::I64,
+            b"uuid" => Self::Uuid,
+            b"hex" => Self::Hex,
+            b"alnum" => Self::Alnum,
+            _ => match text
+                .strip_prefix(b"len(")
+                .and_then(|t| t.strip_suffix(b")"))
+            {
+                Some(range) => {
+                    let range = std::str::from_utf8(range).ok()?;
+                    let (min, max) = range.split_once(',')?;
+                    let min = min.trim().parse().ok()?;
+                    let max = max.trim().parse().ok()?;
+                    if min > max {
+                        return None;
+                    }
+                    Self::Len(min, max)
+                }
+                None => Self::Regex(Regex::new(text)?),
+            },
+        })
+    }
+
+    /// Key ordering constraints the same no matter in which order they are inserted.
+    pub(crate) fn sort_key(&self) -> (u8, usize, usize, &[u8]) {
+        match self {
+            Self::U64 => (0, 0, 0, b""),
+            Self::I64 => (1, 0, 0, b""),
+            Self::Uuid => (2, 0, 0, b""),
+            Self::Hex => (3, 0, 0, b""),
+            Self::Alnum => (4, 0, 0, b""),
+            Self::Len(min, max) => (5, *min, *max, b""),
+            Self::Regex(regex) => (6, 0, 0, regex.pattern()),
+        }
+    }
+
+    pub(crate) fn is_match(&self, value: &[u8]) -> bool {
+        match self {
+            Self::U64 => is_digits(value) && parse::<u64>(value),
+            Self::I64 => {
+                let digits = value.strip_prefix(b"-").unwrap_or(value);
+                is_digits(digits) && parse::<i64>(value)
+            }
+            Self::Uuid => {
+                value.len() == 36
+                    && value.iter().enumerate().all(|(i, b)| match i {
+                        8 | 13 | 18 | 23 => *b == b'-',
+                        _ => b.is_ascii_hexdigit(),
+                    })
+            }
+            Self::Hex => !value.is_empty() && value.iter().all(u8::is_ascii_hexdigit),
+            Self::Alnum => !value.is_empty() && value.iter().all(u8::is_ascii_alphanumeric),
+            Self::Len(min, max) => (*min..=*max).contains(&value.len()),
+            Self::Regex(regex) => regex.is_match(value),
+        }
+    }
+}
+
+impl PartialEq for Constraint {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Len(a0, a1), Self::Len(b0, b1)) => a0 == b0 && a1 == b1,
+            (Self::Regex(a), Self::Regex(b)) => a.pattern() == b.pattern(),
+            _ => mem::discriminant(self) == mem::discriminant(other),
+        }
+    }
+}
+
+#[inline]
+fn is_digits(value: &[u8]) -> bool {
+    !value.is_empty() && value.iter().all(u8::is_ascii_digit)
+}
+
+/// Check the value fits in `N`, the input must be ASCII.
+#[inline]
+fn parse<N: std::str::FromStr>(value: &[u8]) -> bool {
+    unsafe { std::str::from_utf8_unchecked(value) }
+        .parse::<N>()
+        .is_ok()
+}
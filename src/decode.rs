@@ -0,0 +1,169 @@
+use std::borrow::Cow;
+
+use crate::SmallVec;
+
+pub type ParamsDecoded<'k, 'v> = SmallVec<(&'k [u8], Cow<'v, [u8]>)>;
+
+/// How to treat `%2F` (an encoded `/`, or the separator set by `Tree::with_separator`) inside
+/// params and catch alls when decoding them.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum EncodedSlash {
+    /// Treat the path as not found.
+    #[default]
+    Reject,
+    /// Keep `%2F` as is and decode everything else.
+    Keep,
+    /// Decode it to `/` like everything else.
+    Decode,
+}
+
+/// Percent-decode the input. Malformed escapes are kept as is.
+/// Nothing is allocated if there is no escape.
+pub fn percent_decode(input: &[u8]) -> Cow<'_, [u8]> {
+    decode_with(input, |_| true)
+}
+
+/// Decode every escape except the ones of the separator `sep` and `%25`, which are normalized to
+/// upper case. Paths equal in this form are the same path no matter which bytes are escaped,
+/// and the segment structure and escaped `%` are not changed by the decoding.
+pub(crate) fn canonicalize(input: &[u8], sep: u8) -> Cow<'_, [u8]> {
+    decode_with(input, |b| is_canonical_decoded(b, sep))
+}
+
+/// Offset in `input` of the byte at `offset` in its canonical form.
+pub(crate) fn raw_offset(input: &[u8], offset: usize, sep: u8) -> usize {
+    let mut raw = 0;
+    for _ in 0..offset {
+        raw += raw_len(&input[raw..], sep);
+    }
+    raw
+}
+
+/// Length in `input` of the first byte of its canonical form.
+#[inline]
+pub(crate) fn raw_len(input: &[u8], sep: u8) -> usize {
+    match input {
+        [b'%', hi, lo, ..] => match (hex(*hi), hex(*lo)) {
+            (Some(hi), Some(lo)) if is_canonical_decoded(hi << 4 | lo, sep) => 3,
+            _ => 1,
+        },
+        _ => 1,
+    }
+}
+
+#[inline]
+fn is_canonical_decoded(b: u8, sep: u8) -> bool {
+    b != sep && b != b'%'
+}
+
+#[inline]
+fn hex(b: u8) -> Option<u8> {
+    match b {
+        b'0'..=b'9' => Some(b - b'0'),
+        b'a'..=b'f' => Some(b - b'a' + 10),
+        b'A'..=b'F' => Some(b - b'A' + 10),
+        _ => None,
+    }
+}
+
+/// Decode a param value according to the policy for escapes of the separator `sep`, `None` if
+/// it is rejected.
+pub(crate) fn decode_param(value: &[u8], slash: EncodedSlash, sep: u8) -> Option<Cow<'_, [u8]>> {
+    match slash {
+        EncodedSlash::Reject => {
+            if memchr::memchr_iter(b'%', value).any(|idx| {
+                let hex = |at| value.get(at).copied().and_then(hex);
+                matches!((hex(idx + 1), hex(idx + 2)), (Some(hi), Some(lo)) if hi << 4 | lo == sep)
+            }) {
+                return None;
+            }
+            Some(percent_decode(value))
+        }
+        EncodedSlash::Keep => Some(decode_with(value, |b| b != sep)),
+        EncodedSlash::Decode => Some(percent_decode(value)),
+    }
+}
+
+/// Decode escapes of bytes accepted by `decode`, others are kept with upper case hex digits.
+fn decode_with(input: &[u8], decode: impl Fn(u8) -> bool) -> Cow<'_, [u8]> {
+    let mut out: Option<Vec<u8>> = None;
+    let mut copied = 0;
+    for idx in memchr::memchr_iter(b'%', input) {
+        if idx < copied {
+            continue;
+        }
+        let (Some(hi), Some(lo)) = (
+            input.get(idx + 1).copied().and_then(hex),
+            input.get(idx + 2).copied().and_then(hex),
+        ) else {
+            continue;
+        };
+        let byte = hi << 4 | lo;
+        let escape = &input[idx..idx + 3];
+        let replaced = if decode(byte) {
+            &[byte][..]
+        } else if escape[1..].iter().any(u8::is_ascii_lowercase) {
+            &[
+                b'%',
+                escape[1].to_ascii_uppercase(),
+                escape[2].to_ascii_uppercase(),
+            ][..]
+        } else {
+            continue;
+        };
+        let out = out.get_or_insert_with(|| Vec::with_capacity(input.len()));
+        out.extend_from_slice(&input[copied..idx]);
+        out.extend_from_slice(replaced);
+        copied = idx + 3;
+    }
+    match out {
+        None => Cow::Borrowed(input),
+        Some(mut out) => {
+            out.extend_from_slice(&input[copied..]);
+            Cow::Owned(out)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode() {
+        assert!(matches!(
+            percent_decode(b"/plain"),
+            Cow::Borrowed(b"/plain")
+        ));
+        assert_eq!(&*percent_decode(b"/my%20doc"), b"/my doc");
+        assert_eq!(&*percent_decode(b"%2f%2F%25"), b"//%");
+        // malformed escapes are kept
+        assert_eq!(&*percent_decode(b"100%"), b"100%");
+        assert_eq!(&*percent_decode(b"%zz%4"), b"%zz%4");
+        assert_eq!(&*percent_decode(b"%%41"), b"%A");
+
+        assert_eq!(&*canonicalize(b"/a%20b%2fc%25%41", b'/'), b"/a b%2Fc%25A");
+        assert!(matches!(canonicalize(b"/a%2Fb", b'/'), Cow::Borrowed(_)));
+        // `/a b%2Fc%25A` from above
+        assert_eq!(raw_offset(b"/a%20b%2fc%25%41", 3, b'/'), 5);
+        assert_eq!(raw_offset(b"/a%20b%2fc%25%41", 12, b'/'), 16);
+        // The separator is kept encoded instead of `/`.
+        assert_eq!(&*canonicalize(b"a%2eb%2fc", b'.'), b"a%2Eb/c");
+        assert_eq!(raw_offset(b"a%2eb%2fc", 6, b'.'), 8);
+
+        assert_eq!(decode_param(b"a%2fb", EncodedSlash::Reject, b'/'), None);
+        assert_eq!(decode_param(b"a%2eb", EncodedSlash::Reject, b'.'), None);
+        assert_eq!(
+            decode_param(b"a%2fb", EncodedSlash::Reject, b'.').as_deref(),
+            Some(&b"a/b"[..])
+        );
+        assert_eq!(
+            decode_param(b"a%2fb%20", EncodedSlash::Keep, b'/').as_deref(),
+            Some(&b"a%2Fb "[..])
+        );
+        assert_eq!(
+            decode_param(b"a%2Fb%25", EncodedSlash::Decode, b'/').as_deref(),
+            Some(&b"a/b%"[..])
+        );
+    }
+}
@@ -1,17 +1,102 @@
-#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+use std::fmt;
+
+/// Error returned when a route cannot be inserted.
+/// `Display` shows the reason and points at the offending byte of the route.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub struct InsertError {
-    _priv: (),
+    kind: InsertErrorKind,
+    route: Vec<u8>,
+    offset: usize,
+}
+
+/// Why a route was rejected.
+#[non_exhaustive]
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum InsertErrorKind {
+    /// The route is already inserted, maybe with different param names.
+    Conflict {
+        with: Vec<u8>,
+    },
+    EmptyParamName,
+    IllegalParamName,
+    /// A catch all is followed by something other than `/`.
+    CatchAllNotAtEnd,
+    /// A param is directly followed by another param, so there is no static text to end it.
+    AdjacentParams,
+    DuplicateParamName,
+    /// The constraint in `:name<constraint>` is not closed, empty or invalid.
+    InvalidConstraint,
+    MultipleCatchAlls,
+    /// A topic filter wildcard `+` or `#` is not a whole level, or `#` is not the last level.
+    InvalidWildcard,
+    /// The route is empty.
+    EmptyRoute,
+    /// A route has too many optional parts, expanding into more than 256 routes.
+    TooManyOptionals,
 }
 
 impl InsertError {
-    pub(crate) const fn new() -> Self {
-        Self { _priv: () }
+    pub(crate) fn new(kind: InsertErrorKind, route: &[u8], offset: usize) -> Self {
+        Self {
+            kind,
+            route: route.to_vec(),
+            offset,
+        }
+    }
+
+    #[inline]
+    pub fn kind(&self) -> &InsertErrorKind {
+        &self.kind
+    }
+
+    /// The rejected route.
+    #[inline]
+    pub fn route(&self) -> &[u8] {
+        &self.route
+    }
+
+    /// Byte offset of the offending part in the route.
+    #[inline]
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+}
+
+impl fmt::Display for InsertErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InsertErrorKind::Conflict { with } => write!(
+                f,
+                "conflicts with route `{}`",
+                String::from_utf8_lossy(with)
+            ),
+            InsertErrorKind::EmptyParamName => f.write_str("empty param name"),
+            InsertErrorKind::IllegalParamName => f.write_str("illegal character in param name"),
+            InsertErrorKind::CatchAllNotAtEnd => {
+                f.write_str("catch all is not followed by `/` or the end of the route")
+            }
+            InsertErrorKind::AdjacentParams => {
+                f.write_str("param is directly followed by another param")
+            }
+            InsertErrorKind::DuplicateParamName => f.write_str("duplicate param name"),
+            InsertErrorKind::InvalidConstraint => f.write_str("invalid param constraint"),
+            InsertErrorKind::MultipleCatchAlls => f.write_str("more than one catch all"),
+            InsertErrorKind::InvalidWildcard => f.write_str("invalid topic filter wildcard"),
+            InsertErrorKind::EmptyRoute => f.write_str("empty route"),
+            InsertErrorKind::TooManyOptionals => f.write_str("too many optional parts"),
+        }
     }
 }
 
-impl std::fmt::Display for InsertError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_str("insert route failed")
+impl fmt::Display for InsertError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let offset = self.offset.min(self.route.len());
+        let column = String::from_utf8_lossy(&self.route[..offset])
+            .chars()
+            .count();
+        writeln!(f, "insert route failed: {}", self.kind)?;
+        writeln!(f, "    {}", String::from_utf8_lossy(&self.route))?;
+        write!(f, "    {:column$}^", "")
     }
 }
 impl std::error::Error for InsertError {}
@@ -0,0 +1,280 @@
+use crate::{
+    error::InsertError,
+    parser::{closing_angle, is_param_byte},
+    Params, Tree,
+};
+
+/// Router which matches hosts label by label from the right, e.g. for the `Host` header or
+/// TLS SNI.
+/// - `api.example.com` matches the host exactly.
+/// - `:tenant.example.com` captures one label, `:tenant-dev.example.com` part of one.
+/// - `*.example.com` captures one or more labels as `**`, `*sub.example.com` as `sub`.
+///
+/// Hosts are matched without the port (see `with_port_stripping`) and trailing dot, and
+/// lowercased, with punycode labels like `xn--bcher-kva` decoded to `bücher`; static parts of
+/// the routes are normalized the same way on insert. Hosts with empty labels never match.
+/// `:` in IPv6 literals must be escaped, like `[\:\:1]`.
+#[derive(Debug, Clone)]
+pub struct HostRouter<T> {
+    // Keyed by labels from the right, so `:tenant.example.com` is `com.example.:tenant`.
+    tree: Tree<T>,
+    strip_port: bool,
+}
+
+impl<T> Default for HostRouter<T> {
+    #[inline(always)]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> HostRouter<T> {
+    #[inline(always)]
+    pub const fn new() -> Self {
+        Self {
+            tree: Tree::new().with_separator(b'.'),
+            strip_port: true,
+        }
+    }
+
+    /// Whether to drop the port of the host before matching, on by default. Without it, the
+    /// port must be part of the route, like `example.com\:8080`.
+    #[inline(always)]
+    pub const fn with_port_stripping(mut self, strip: bool) -> Self {
+        self.strip_port = strip;
+        self
+    }
+
+    /// Insert the host route. Like hosts in `at`, the route is taken without a trailing dot,
+    /// and without a port such as `example.com:8080` when ports are stripped.
+    pub fn insert(&mut self, route: &[u8], val: T) -> Result<(), InsertError> {
+        let host = match self.strip_port {
+            true => strip_route_port(route),
+            false => route,
+        };
+        let host = host.strip_suffix(b".").unwrap_or(host);
+        let mut key = Vec::with_capacity(host.len() + 1);
+        for (idx, label) in host.rsplit(|b| *b == b'.').enumerate() {
+            if idx > 0 {
+                key.push(b'.');
+            }
+            match label {
+                b"*" => key.extend_from_slice(b"**"),
+                [b'*', ..] => key.extend_from_slice(label),
+                _ => match decode_punycode(label) {
+                    Some(label) => key.extend_from_slice(label.as_bytes()),
+                    None => lowercase_route(label, &mut key),
+                },
+            }
+        }
+        self.tree.insert(&key, val).map_err(|err| {
+            let offset = host_offset(host, &key, err.offset());
+            InsertError::new(err.kind().clone(), route, offset)
+        })
+    }
+
+    /// Match the host, which may have a port. `buf` holds the lowercased host the params are
+    /// borrowed from.
+    pub fn at<'n, 'p>(
+        &'n self,
+        host: &[u8],
+        buf: &'p mut Vec<u8>,
+    ) -> Option<(&'n T, Params<'n, 'p>)> {
+        let host = match self.strip_port {
+            true => strip_port(host),
+            false => host,
+        };
+        let host = host.strip_suffix(b".").unwrap_or(host);
+        if host.contains(&b'/') {
+            return None;
+        }
+        buf.clear();
+        for (idx, label) in host.split(|b| *b == b'.').enumerate() {
+            if label.is_empty() {
+                return None;
+            }
+            if idx > 0 {
+                buf.push(b'.');
+            }
+            match decode_punycode(label) {
+                Some(label) => buf.extend_from_slice(label.as_bytes()),
+                None => lowercase(label, buf),
+            }
+        }
+        let len = buf.len();
+        buf.reserve(len);
+        let mut end = len;
+        for idx in (0..len).rev() {
+            if buf[idx] == b'.' {
+                buf.extend_from_within(idx + 1..end);
+                buf.push(b'.');
+                end = idx;
+            }
+        }
+        buf.extend_from_within(..end);
+
+        let buf: &'p [u8] = buf;
+        let (host, key) = buf.split_at(len);
+        let (value, mut params) = self.tree.at(key)?;
+        // Labels are reversed, so a value spanning labels is still contiguous in the host.
+        for (_, v) in params.iter_mut().filter(|(_, v)| !v.is_empty()) {
+            let offset = v.as_ptr() as usize - key.as_ptr() as usize;
+            let first = host_offset(host, key, offset);
+            let last = host_offset(host, key, offset + v.len() - 1);
+            *v = &host[first.min(last)..=first.max(last)];
+        }
+        Some((value, params))
+    }
+}
+
+impl<T> HostRouter<Tree<T>> {
+    /// Match the host, then the path in the tree of the host.
+    /// Host params come before path params.
+    pub fn at_path<'n, 'p>(
+        &'n self,
+        host: &[u8],
+        path: &'p [u8],
+        buf: &'p mut Vec<u8>,
+    ) -> Option<(&'n T, Params<'n, 'p>)> {
+        let (tree, mut params) = self.at(host, buf)?;
+        let (value, path_params) = tree.at(path)?;
+        params.extend(path_params);
+        Some((value, params))
+    }
+}
+
+/// Drop the port, keeping IPv6 literals like `[::1]` or bare `::1` whole.
+#[inline]
+fn strip_port(host: &[u8]) -> &[u8] {
+    let end = match host.first() {
+        Some(b'[') => memchr::memchr(b']', host).map(|idx| idx + 1),
+        _ => match memchr::memchr(b':', host) {
+            Some(idx) if memchr::memchr(b':', &host[idx + 1..]).is_none() => Some(idx),
+            _ => None,
+        },
+    };
+    &host[..end.unwrap_or(host.len())]
+}
+
+/// Like `strip_port`, but for routes, where `:` starting a label is a param and an escaped `:`
+/// is literal.
+#[inline]
+fn strip_route_port(route: &[u8]) -> &[u8] {
+    let Some(idx) = memchr::memrchr(b':', route) else {
+        return route;
+    };
+    let (before, port) = (&route[..idx], &route[idx + 1..]);
+    let is_port = !port.is_empty()
+        && port.iter().all(u8::is_ascii_digit)
+        && !matches!(before.last(), None | Some(b'.' | b'\\'))
+        && match before.first() {
+            Some(b'[') => before.last() == Some(&b']'),
+            _ => !before.contains(&b':'),
+        };
+    match is_port {
+        true => before,
+        false => route,
+    }
+}
+
+/// Lowercase the host, non-ASCII ones by Unicode rules if they are valid UTF-8.
+#[inline]
+fn lowercase(host: &[u8], out: &mut Vec<u8>) {
+    match std::str::from_utf8(host) {
+        Ok(host) if !host.is_ascii() => out.extend_from_slice(host.to_lowercase().as_bytes()),
+        _ => out.extend(host.iter().map(u8::to_ascii_lowercase)),
+    }
+}
+
+/// Decode a punycode label like `xn--bcher-kva` (RFC 3492) and lowercase it. `None` if the
+/// label is not punycode, is invalid, or decodes to something which is not a single label.
+fn decode_punycode(label: &[u8]) -> Option<String> {
+    const BASE: u32 = 36;
+    const T_MIN: u32 = 1;
+    const T_MAX: u32 = 26;
+
+    fn adapt(delta: u32, points: u32, first: bool) -> u32 {
+        let mut delta = if first { delta / 700 } else { delta / 2 };
+        delta += delta / points;
+        let mut k = 0;
+        while delta > (BASE - T_MIN) * T_MAX / 2 {
+            delta /= BASE - T_MIN;
+            k += BASE;
+        }
+        k + (BASE - T_MIN + 1) * delta / (delta + 38)
+    }
+
+    let input = match label {
+        [b'x' | b'X', b'n' | b'N', b'-', b'-', input @ ..] if input.is_ascii() => input,
+        _ => return None,
+    };
+    let (mut output, rest): (Vec<char>, _) = match memchr::memrchr(b'-', input) {
+        Some(idx) => (
+            input[..idx].iter().map(|b| *b as char).collect(),
+            &input[idx + 1..],
+        ),
+        None => (Vec::new(), input),
+    };
+    let (mut n, mut i, mut bias) = (128u32, 0u32, 72);
+    let mut digits = rest.iter();
+    while digits.len() > 0 {
+        let old_i = i;
+        let mut w = 1u32;
+        let mut k = BASE;
+        loop {
+            let digit = match *digits.next()? {
+                b @ b'a'..=b'z' => b - b'a',
+                b @ b'A'..=b'Z' => b - b'A',
+                b @ b'0'..=b'9' => b - b'0' + 26,
+                _ => return None,
+            } as u32;
+            i = i.checked_add(digit.checked_mul(w)?)?;
+            let t = k.saturating_sub(bias).clamp(T_MIN, T_MAX);
+            if digit < t {
+                break;
+            }
+            w = w.checked_mul(BASE - t)?;
+            k += BASE;
+        }
+        let len = output.len() as u32 + 1;
+        bias = adapt(i - old_i, len, old_i == 0);
+        n = n.checked_add(i / len)?;
+        i %= len;
+        output.insert(i as usize, char::from_u32(n)?);
+        i += 1;
+    }
+    let label: String = output.into_iter().collect::<String>().to_lowercase();
+    match label.is_empty() || label.bytes().any(|b| matches!(b, b'.' | b'/' | b':')) {
+        true => None,
+        false => Some(label),
+    }
+}
+
+/// Lowercase a label of a route but its param names and constraints.
+fn lowercase_route(label: &[u8], out: &mut Vec<u8>) {
+    let mut rest = label;
+    while let Some(idx) = memchr::memchr(b':', rest) {
+        lowercase(&rest[..idx], out);
+        let mut end = idx + 1;
+        while rest.get(end).copied().is_some_and(is_param_byte) {
+            end += 1;
+        }
+        if rest.get(end) == Some(&b'<') {
+            end += closing_angle(&rest[end..]).map_or(rest.len() - end, |close| close + 1);
+        }
+        out.extend_from_slice(&rest[idx..end]);
+        rest = &rest[end..];
+    }
+    lowercase(rest, out);
+}
+
+/// Map an offset in `key` to the same byte of `host`, clamped to the label.
+fn host_offset(host: &[u8], key: &[u8], offset: usize) -> usize {
+    let before = &key[..offset.min(key.len())];
+    let label = before.iter().filter(|b| **b == b'.').count();
+    let within = offset - memchr::memrchr(b'.', before).map_or(0, |idx| idx + 1);
+    match host.rsplit(|b| *b == b'.').nth(label) {
+        Some(part) => part.as_ptr() as usize - host.as_ptr() as usize + within.min(part.len()),
+        None => host.len(),
+    }
+}
@@ -1,5 +1,10 @@
+mod constraint;
+mod decode;
 mod error;
+mod host;
+mod method;
 mod parser;
+mod topic;
 mod tree;
 mod util;
 
@@ -9,6 +14,10 @@ pub type ParamsStr<'k, 'v> = SmallVec<(&'k str, &'v str)>;
 pub type ParamsOwned = SmallVec<(Vec<u8>, Vec<u8>)>;
 pub type ParamsStrOwned = SmallVec<(String, String)>;
 
-pub use error::InsertError;
-pub use tree::Tree;
-pub use util::{ParamsConvertOwned, ParamsConvertStr, ParamsGet, ParamsGetOwned};
+pub use decode::{percent_decode, EncodedSlash, ParamsDecoded};
+pub use error::{InsertError, InsertErrorKind};
+pub use host::HostRouter;
+pub use method::{Allowed, MethodLookup, MethodRouter};
+pub use topic::TopicTree;
+pub use tree::{IntoIter, Iter, IterMut, Lookup, Match, Matches, RouteId, Tree};
+pub use util::{clean_path, ParamsConvertOwned, ParamsConvertStr, ParamsGet, ParamsGetOwned};
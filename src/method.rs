@@ -0,0 +1,233 @@
+use crate::{
+    error::{InsertError, InsertErrorKind},
+    Params, SmallVec, Tree,
+};
+
+const HEAD: &[u8] = b"HEAD";
+const GET: &[u8] = b"GET";
+const OPTIONS: &[u8] = b"OPTIONS";
+// The order `Allow` lists them in.
+const STANDARD: [&[u8]; 9] = [
+    GET, HEAD, b"POST", b"PUT", b"DELETE", b"CONNECT", OPTIONS, b"TRACE", b"PATCH",
+];
+
+/// Router which matches the request method along with the path.
+/// Methods are compared as bytes, so custom methods are supported as well.
+/// - `HEAD` falls back to the `GET` route.
+/// - `OPTIONS` is answered with the allowed methods unless it has its own route.
+///
+/// All methods share one tree. A lookup goes through the routes matching the path in priority
+/// order until one has the method, so with `GET /users/:id` and `POST /users/new`,
+/// `GET /users/new` is routed to `GET /users/:id`.
+#[derive(Debug, Clone)]
+pub struct MethodRouter<T> {
+    tree: Tree<MethodTable<T>>,
+}
+
+/// Values of a route by method.
+#[derive(Debug, Clone)]
+struct MethodTable<T> {
+    // Bit `i` is set if `STANDARD[i]` has a value.
+    bits: u16,
+    // Values of the methods in `bits`, in `STANDARD` order.
+    standard: Vec<Handler<T>>,
+    // In insertion order.
+    custom: Vec<(Vec<u8>, Handler<T>)>,
+}
+
+#[derive(Debug, Clone)]
+struct Handler<T> {
+    value: T,
+    // Param names of the route inserted for the method, if they differ from the tree's.
+    names: Option<Vec<Vec<u8>>>,
+}
+
+impl<T> MethodTable<T> {
+    #[inline(always)]
+    const fn new() -> Self {
+        Self {
+            bits: 0,
+            standard: Vec::new(),
+            custom: Vec::new(),
+        }
+    }
+
+    /// Index in `standard` of the value at bit `bit`.
+    #[inline(always)]
+    const fn index(&self, bit: usize) -> usize {
+        (self.bits & ((1 << bit) - 1)).count_ones() as usize
+    }
+
+    #[inline]
+    fn get(&self, method: &[u8]) -> Option<&Handler<T>> {
+        match rank(method) {
+            bit if bit < STANDARD.len() => {
+                (self.bits & (1 << bit) != 0).then(|| &self.standard[self.index(bit)])
+            }
+            _ => self
+                .custom
+                .iter()
+                .find_map(|(m, value)| (m == method).then_some(value)),
+        }
+    }
+
+    /// Insert the value, or give it back if the method already has one.
+    fn insert(&mut self, method: &[u8], value: Handler<T>) -> Result<(), Handler<T>> {
+        match rank(method) {
+            bit if bit < STANDARD.len() => {
+                if self.bits & (1 << bit) != 0 {
+                    return Err(value);
+                }
+                self.standard.insert(self.index(bit), value);
+                self.bits |= 1 << bit;
+            }
+            _ => {
+                if self.custom.iter().any(|(m, _)| m == method) {
+                    return Err(value);
+                }
+                self.custom.push((method.to_vec(), value));
+            }
+        }
+        Ok(())
+    }
+
+    fn push_allowed<'n>(&'n self, allowed: &mut Allowed<'n>) {
+        for (bit, method) in STANDARD.iter().enumerate() {
+            if self.bits & (1 << bit) != 0 {
+                allowed.push(method);
+            }
+        }
+        for (method, _) in self.custom.iter() {
+            allowed.push(method);
+        }
+    }
+}
+
+/// Result of `MethodRouter::at`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MethodLookup<'n, 'p, T> {
+    Found(&'n T, Params<'n, 'p>),
+    /// The path matches with other methods only.
+    MethodNotAllowed {
+        allowed: Allowed<'n>,
+    },
+    /// `OPTIONS` for a path without its own `OPTIONS` route.
+    Options {
+        allowed: Allowed<'n>,
+    },
+    NotFound,
+}
+
+/// Methods allowed for a path, including the implied `HEAD` and `OPTIONS`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Allowed<'n> {
+    methods: SmallVec<&'n [u8]>,
+}
+
+impl<'n> Allowed<'n> {
+    #[inline]
+    pub fn iter(&self) -> impl Iterator<Item = &'n [u8]> + '_ {
+        self.methods.iter().copied()
+    }
+
+    #[inline]
+    pub fn contains(&self, method: &[u8]) -> bool {
+        self.methods.contains(&method)
+    }
+
+    /// Value of the `Allow` header, like `GET, HEAD, OPTIONS`.
+    pub fn header_value(&self) -> Vec<u8> {
+        self.methods.join(&b", "[..])
+    }
+
+    fn push(&mut self, method: &'n [u8]) {
+        if !self.contains(method) {
+            self.methods.push(method);
+        }
+    }
+
+    /// Add the implied methods and sort the standard ones first.
+    fn finish(mut self) -> Self {
+        if self.contains(GET) {
+            self.push(HEAD);
+        }
+        self.push(OPTIONS);
+        self.methods.sort_by_key(|method| rank(method));
+        self
+    }
+}
+
+impl<T> Default for MethodRouter<T> {
+    #[inline(always)]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> MethodRouter<T> {
+    #[inline(always)]
+    pub const fn new() -> Self {
+        Self { tree: Tree::new() }
+    }
+
+    /// Insert the route for the method.
+    /// Methods of a route may name its params differently, like `GET /users/:id` and
+    /// `DELETE /users/:name`.
+    pub fn insert(&mut self, method: &[u8], route: &[u8], val: T) -> Result<(), InsertError> {
+        let shared = self.tree.get_or_insert_with(route, MethodTable::new)?;
+        let names = shared.names;
+        shared
+            .value
+            .insert(method, Handler { value: val, names })
+            .map_err(|_| {
+                InsertError::new(
+                    InsertErrorKind::Conflict {
+                        with: shared.route.to_vec(),
+                    },
+                    route,
+                    0,
+                )
+            })
+    }
+
+    pub fn at<'n, 'p>(&'n self, method: &[u8], path: &'p [u8]) -> MethodLookup<'n, 'p, T> {
+        let mut allowed = Allowed::default();
+        let mut handler = None;
+        let found = self.tree.at_with(path, |table, _| {
+            handler = match table.get(method) {
+                None if method == HEAD => table.get(GET),
+                handler => handler,
+            };
+            if handler.is_none() {
+                table.push_allowed(&mut allowed);
+            }
+            handler.is_some()
+        });
+        if let (Some((_, mut params)), Some(handler)) = (found, handler) {
+            if let Some(names) = &handler.names {
+                for ((name, _), renamed) in params.iter_mut().zip(names) {
+                    *name = renamed;
+                }
+            }
+            return MethodLookup::Found(&handler.value, params);
+        }
+
+        if allowed.methods.is_empty() {
+            return MethodLookup::NotFound;
+        }
+        let allowed = allowed.finish();
+        match method {
+            OPTIONS => MethodLookup::Options { allowed },
+            _ => MethodLookup::MethodNotAllowed { allowed },
+        }
+    }
+}
+
+/// Position of the method in `Allow`, custom methods after the standard ones.
+#[inline]
+fn rank(method: &[u8]) -> usize {
+    STANDARD
+        .iter()
+        .position(|m| *m == method)
+        .unwrap_or(STANDARD.len())
+}
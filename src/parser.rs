@@ -1,9 +1,15 @@
-use crate::{error::InsertError, tree::next_param};
+use std::mem;
+
+use crate::error::{InsertError, InsertErrorKind};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub(crate) enum Segment<'a> {
     Static(&'a [u8]),
     Param(&'a [u8]),
+    /// Param with a constraint: `:name<constraint>`.
+    Constrained(&'a [u8], &'a [u8]),
+    /// Catch all which has at least one non-empty segment: `:name+`.
+    OneOrMore(&'a [u8]),
     CatchAll(&'a [u8]),
 }
 
@@ -13,19 +19,29 @@ impl<'a> Segment<'a> {
         match self {
             Segment::Static(_) => None,
             Segment::Param(name) => Some(name),
+            Segment::Constrained(name, _) => Some(name),
+            Segment::OneOrMore(name) => Some(name),
             Segment::CatchAll(name) => Some(name),
         }
     }
 }
 
 pub(crate) struct SegmentsIter<'a> {
+    // The whole route, for error reporting.
+    route: &'a [u8],
     pub(crate) inner: &'a [u8],
+    // Ends catch alls.
+    sep: u8,
 }
 
 impl<'a> SegmentsIter<'a> {
     #[inline(always)]
-    pub(crate) const fn new(inner: &'a [u8]) -> Self {
-        Self { inner }
+    pub(crate) const fn new(inner: &'a [u8], sep: u8) -> Self {
+        Self {
+            route: inner,
+            inner,
+            sep,
+        }
     }
 }
 
@@ -33,43 +49,104 @@ impl<'a> Iterator for SegmentsIter<'a> {
     type Item = Result<Segment<'a>, InsertError>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        macro_rules! check_empty {
-            ($var:expr) => {
-                if $var.is_empty() {
-                    return Some(Err(InsertError::new()));
-                }
-            };
-        }
-
-        macro_rules! check_special {
-            ($var:expr) => {
-                if memchr::memchr3(b'/', b':', b'*', $var).is_some() {
-                    return Some(Err(InsertError::new()));
-                }
+        // Fail with the error pointing at the start of `$at`, a suffix of the route.
+        macro_rules! fail {
+            ($kind:ident, $at:expr) => {
+                return Some(Err(InsertError::new(
+                    InsertErrorKind::$kind,
+                    self.route,
+                    self.route.len() - $at.len(),
+                )))
             };
         }
 
         match self.inner.first()? {
             b':' => {
                 let path = unsafe { self.inner.split_at_unchecked(1).1 };
-                let (param_name, rest) = next_param(path);
-                check_special!(param_name);
-                check_empty!(param_name);
+                let len = path.iter().position(|b| !is_param_byte(*b));
+                let (param_name, mut rest) =
+                    unsafe { path.split_at_unchecked(len.unwrap_or(path.len())) };
+                if param_name.is_empty() {
+                    match rest.first() {
+                        // A name is missing only if nothing which could be one follows.
+                        Some(&b) if b != self.sep && !matches!(b, b'<' | b'+') => {
+                            fail!(IllegalParamName, rest)
+                        }
+                        _ => fail!(EmptyParamName, self.inner),
+                    }
+                }
+                let mut constraint = None;
+                if rest.first() == Some(&b'<') {
+                    let Some(end) = closing_angle(rest) else {
+                        fail!(InvalidConstraint, rest);
+                    };
+                    let (text, after) = unsafe { rest.split_at_unchecked(end) };
+                    let text = unsafe { text.split_at_unchecked(1).1 };
+                    if text.is_empty() {
+                        fail!(InvalidConstraint, rest);
+                    }
+                    constraint = Some(text);
+                    rest = unsafe { after.split_at_unchecked(1).1 };
+                } else if rest.first() == Some(&b'+') {
+                    let rest = unsafe { rest.split_at_unchecked(1).1 };
+                    // Like a catch all, it ends at a separator.
+                    if rest.first().is_some_and(|b| *b != self.sep) {
+                        fail!(CatchAllNotAtEnd, rest);
+                    }
+                    self.inner = rest;
+                    return Some(Ok(Segment::OneOrMore(param_name)));
+                }
+                match rest.first() {
+                    // `?` marks optional params, which only `Tree::insert_optional` accepts.
+                    Some(b'?') => fail!(IllegalParamName, rest),
+                    // A param directly followed by another one has no static text to end it.
+                    Some(b':' | b'*') => fail!(AdjacentParams, rest),
+                    _ => (),
+                }
                 self.inner = rest;
-                Some(Ok(Segment::Param(param_name)))
+                Some(Ok(match constraint {
+                    Some(text) => Segment::Constrained(param_name, text),
+                    None => Segment::Param(param_name),
+                }))
             }
             b'*' => {
-                let param_name = unsafe { self.inner.split_at_unchecked(1).1 };
-                check_special!(param_name);
-                check_empty!(param_name);
-                self.inner = &[];
+                // `**` is an anonymous catch all, captured as `**`.
+                let (param_name, rest) = if self.inner.get(1) == Some(&b'*') {
+                    unsafe { self.inner.split_at_unchecked(2) }
+                } else {
+                    let path = unsafe { self.inner.split_at_unchecked(1).1 };
+                    let len = path.iter().position(|b| !is_param_byte(*b));
+                    unsafe { path.split_at_unchecked(len.unwrap_or(path.len())) }
+                };
+                if param_name.is_empty() {
+                    match rest.first() {
+                        Some(&b) if b != self.sep => fail!(IllegalParamName, rest),
+                        _ => fail!(EmptyParamName, self.inner),
+                    }
+                }
+                // A catch all in the middle of a route ends at a separator.
+                if rest.first().is_some_and(|b| *b != self.sep) {
+                    fail!(CatchAllNotAtEnd, rest);
+                }
+                self.inner = rest;
                 Some(Ok(Segment::CatchAll(param_name)))
             }
+            b'\\' if self.inner.get(1).copied().is_some_and(is_escapable) => {
+                // `\:`, `\*` and `\\` are the literal bytes.
+                let (escape, rest) = unsafe { self.inner.split_at_unchecked(2) };
+                self.inner = rest;
+                Some(Ok(Segment::Static(unsafe {
+                    escape.split_at_unchecked(1).1
+                })))
+            }
             _ => {
-                if let Some(idx) = memchr::memchr2(b':', b'*', self.inner) {
+                let end = memchr::memchr3_iter(b':', b'*', b'\\', self.inner).find(|&idx| {
+                    self.inner[idx] != b'\\'
+                        || self.inner.get(idx + 1).copied().is_some_and(is_escapable)
+                });
+                if let Some(idx) = end {
                     let (segment, rest) = unsafe { self.inner.split_at_unchecked(idx) };
                     self.inner = rest;
-                    check_empty!(segment);
                     return Some(Ok(Segment::Static(segment)));
                 }
                 let inner = self.inner;
@@ -79,3 +156,199 @@ impl<'a> Iterator for SegmentsIter<'a> {
         }
     }
 }
+
+/// Bytes allowed in param names, any other byte ends the name.
+#[inline(always)]
+pub(crate) const fn is_param_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'_'
+}
+
+/// Bytes which are literal when escaped by `\\`.
+#[inline(always)]
+const fn is_escapable(b: u8) -> bool {
+    matches!(b, b':' | b'*' | b'\\')
+}
+
+/// Find the `>` closing the `<` at the start of `input`.
+/// Nested `<...>` pairs (e.g. regex named groups) and backslash escapes are skipped.
+pub(crate) fn closing_angle(input: &[u8]) -> Option<usize> {
+    let mut depth = 0_usize;
+    let mut escaped = false;
+    for (idx, b) in input.iter().enumerate() {
+        match (escaped, b) {
+            (true, _) => escaped = false,
+            (false, b'\\') => escaped = true,
+            (false, b'<') => depth += 1,
+            (false, b'>') => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(idx);
+                }
+            }
+            _ => (),
+        }
+    }
+    None
+}
+
+/// A route expanded from optional parts, with the offset in the original route of each byte.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Expanded {
+    pub route: Vec<u8>,
+    offsets: Vec<usize>,
+}
+
+impl Expanded {
+    #[inline]
+    fn push(&mut self, byte: u8, offset: usize) {
+        self.route.push(byte);
+        self.offsets.push(offset);
+    }
+
+    #[inline]
+    fn extend(&mut self, other: &Expanded) {
+        self.route.extend_from_slice(&other.route);
+        self.offsets.extend_from_slice(&other.offsets);
+    }
+
+    /// Offset in the original route of the byte at `offset`, `end` past the last byte.
+    #[inline]
+    pub fn original_offset(&self, offset: usize, end: usize) -> usize {
+        self.offsets.get(offset).copied().unwrap_or(end)
+    }
+}
+
+/// How many routes a route with optional parts may expand into.
+pub(crate) const MAX_EXPANSIONS: usize = 256;
+
+/// Expand optional groups `(...)?` and optional params `:name?` into every route they describe,
+/// the one with all optional parts present first.
+/// An optional param takes its leading separator with it, so `/docs/:version?` is
+/// `/docs(/:version)?`.
+/// Parentheses not closed by `)?` are kept as static bytes.
+/// Each optional part doubles the routes, so more than `MAX_EXPANSIONS` of them are an error.
+pub(crate) fn expand_optional(route: &[u8], sep: u8) -> Result<Vec<Expanded>, InsertError> {
+    #[inline]
+    fn append(alts: &mut [Expanded], route: &[u8], start: usize, end: usize) {
+        for alt in alts {
+            for (offset, b) in route.iter().enumerate().take(end).skip(start) {
+                alt.push(*b, offset);
+            }
+        }
+    }
+
+    // `at` is the offset of the optional part, for the error.
+    #[inline]
+    fn product(
+        alts: Vec<Expanded>,
+        options: &[Expanded],
+        route: &[u8],
+        at: usize,
+    ) -> Result<Vec<Expanded>, InsertError> {
+        let len = alts.len() * options.len();
+        if len > MAX_EXPANSIONS {
+            return Err(InsertError::new(
+                InsertErrorKind::TooManyOptionals,
+                route,
+                at,
+            ));
+        }
+        let mut out = Vec::with_capacity(len);
+        for alt in alts {
+            for option in options {
+                let mut new = alt.clone();
+                new.extend(option);
+                out.push(new);
+            }
+        }
+        Ok(out)
+    }
+
+    // Each frame holds the offset of the `(` opening it and the expansions of the text since.
+    let mut frames: Vec<(usize, Vec<Expanded>)> = vec![(0, vec![Expanded::default()])];
+    let mut ends_with_slash = false;
+    let mut idx = 0;
+    while let Some(&b) = route.get(idx) {
+        let depth = frames.len();
+        let frame = &mut frames.last_mut().unwrap().1;
+        match b {
+            // Keep escapes for `SegmentsIter`.
+            b'\\' if route.get(idx + 1).copied().is_some_and(is_escapable) => {
+                append(frame, route, idx, idx + 2);
+                ends_with_slash = false;
+                idx += 2;
+            }
+            b'(' => {
+                frames.push((idx, vec![Expanded::default()]));
+                ends_with_slash = false;
+                idx += 1;
+            }
+            b')' if route.get(idx + 1) == Some(&b'?') && depth > 1 => {
+                let (group_open, mut group) = frames.pop().unwrap();
+                group.push(Expanded::default());
+                let (open, parent) = frames.pop().unwrap();
+                frames.push((open, product(parent, &group, route, group_open)?));
+                ends_with_slash = false;
+                idx += 2;
+            }
+            b':' => {
+                let mut end = idx + 1;
+                while route.get(end).copied().is_some_and(is_param_byte) {
+                    end += 1;
+                }
+                if route.get(end) == Some(&b'<') {
+                    let Some(close) = closing_angle(&route[end..]) else {
+                        return Err(InsertError::new(
+                            InsertErrorKind::InvalidConstraint,
+                            route,
+                            end,
+                        ));
+                    };
+                    end += close + 1;
+                }
+                if route.get(end) != Some(&b'?') {
+                    append(frame, route, idx, end);
+                    ends_with_slash = false;
+                    idx = end;
+                    continue;
+                }
+                let mut option = Expanded::default();
+                if ends_with_slash {
+                    let mut offset = idx;
+                    for alt in frame.iter_mut() {
+                        alt.route.pop();
+                        offset = alt.offsets.pop().unwrap_or(offset);
+                    }
+                    option.push(sep, offset);
+                }
+                append(std::slice::from_mut(&mut option), route, idx, end);
+                let alts = mem::take(frame);
+                *frame = product(alts, &[option, Expanded::default()], route, idx)?;
+                ends_with_slash = false;
+                idx = end + 1;
+            }
+            _ => {
+                append(frame, route, idx, idx + 1);
+                ends_with_slash = b == sep;
+                idx += 1;
+            }
+        }
+    }
+
+    // Unclosed `(` are static bytes.
+    while frames.len() > 1 {
+        let (open, group) = frames.pop().unwrap();
+        let options: Vec<Expanded> = group
+            .into_iter()
+            .map(|alt| {
+                let mut option = Expanded::default();
+                option.push(b'(', open);
+                option.extend(&alt);
+                option
+            })
+            .collect();
+        let (parent_open, parent) = frames.pop().unwrap();
+        frames.push((parent_open, product(parent, &options, route, open)?));
+    }
+    Ok(frames.pop().unwrap().1)
+}
@@ -0,0 +1,119 @@
+use crate::{
+    error::{InsertError, InsertErrorKind},
+    Tree,
+};
+
+/// MQTT style topic filters, where a topic is delivered to every matching filter.
+/// - `+` matches one level, which may be empty.
+/// - `#` matches the parent level and any number of levels below it, so `a/#` matches `a`,
+///   `a/` and `a/b/c`. It must be the last level.
+/// - Topics starting with `$` are not matched by filters starting with `+` or `#`.
+///
+/// A filter may be inserted more than once to hold more values.
+#[derive(Debug, Clone)]
+pub struct TopicTree<T> {
+    // Filters are inserted as routes holding indices into `values`, `+` as `:lN` with `N` the
+    // level and `#` as `**`.
+    tree: Tree<Vec<usize>>,
+    // With whether the filter starts with a wildcard.
+    values: Vec<(bool, T)>,
+}
+
+impl<T> Default for TopicTree<T> {
+    #[inline(always)]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> TopicTree<T> {
+    #[inline(always)]
+    pub const fn new() -> Self {
+        Self {
+            tree: Tree::new(),
+            values: Vec::new(),
+        }
+    }
+
+    pub fn insert(&mut self, filter: &[u8], val: T) -> Result<(), InsertError> {
+        let mut route = Vec::with_capacity(filter.len() + 8);
+        let mut multi_level = false;
+        // Start of the last level in `route`.
+        let mut last = 0;
+        for (level, part) in filter.split(|b| *b == b'/').enumerate() {
+            let offset = part.as_ptr() as usize - filter.as_ptr() as usize;
+            let error = || {
+                Err(InsertError::new(
+                    InsertErrorKind::InvalidWildcard,
+                    filter,
+                    offset,
+                ))
+            };
+            if multi_level {
+                return error();
+            }
+            if level > 0 {
+                route.push(b'/');
+            }
+            last = route.len();
+            match part {
+                b"+" => {
+                    route.push(b':');
+                    route.push(b'l');
+                    route.extend_from_slice(level.to_string().as_bytes());
+                }
+                b"#" => {
+                    route.extend_from_slice(b"**");
+                    multi_level = true;
+                }
+                _ if part.iter().any(|b| matches!(b, b'+' | b'#')) => return error(),
+                _ => {
+                    for &b in part {
+                        if matches!(b, b':' | b'*' | b'\\') {
+                            route.push(b'\\');
+                        }
+                        route.push(b);
+                    }
+                }
+            }
+        }
+
+        let idx = self.values.len();
+        self.tree
+            .get_or_insert_with(&route, Vec::new)?
+            .value
+            .push(idx);
+        // `a/#` also matches `a`, and `a/+` matches `a/` as the param cannot be empty.
+        let parent = match &route[last..] {
+            _ if last == 0 => None,
+            // There is no empty topic for `/#` to match.
+            b"**" if last > 1 => Some(&route[..last - 1]),
+            [b':', ..] => Some(&route[..last]),
+            _ => None,
+        };
+        if let Some(parent) = parent {
+            self.tree
+                .get_or_insert_with(parent, Vec::new)?
+                .value
+                .push(idx);
+        }
+        let wildcard_first = matches!(filter.first(), Some(b'+' | b'#'));
+        self.values.push((wildcard_first, val));
+        Ok(())
+    }
+
+    /// Values of every filter matching the topic, in matching priority order and then
+    /// insertion order.
+    pub fn matches<'n, 'p>(&'n self, topic: &'p [u8]) -> impl Iterator<Item = &'n T> + 'p
+    where
+        'n: 'p,
+    {
+        let system = topic.first() == Some(&b'$');
+        self.tree
+            .matches_undecoded(topic)
+            .flat_map(|(indices, _)| indices)
+            .map(|idx| &self.values[*idx])
+            .filter(move |(wildcard_first, _)| !(system && *wildcard_first))
+            .map(|(_, val)| val)
+    }
+}
@@ -1,59 +1,125 @@
-use std::mem;
+use std::{borrow::Cow, mem};
 
 use crate::{
-    error::InsertError,
-    parser::{Segment, SegmentsIter},
+    constraint::Constraint,
+    decode::{canonicalize, decode_param, raw_len, raw_offset, EncodedSlash, ParamsDecoded},
+    error::{InsertError, InsertErrorKind},
+    parser::{expand_optional, is_param_byte, Segment, SegmentsIter},
+    util::clean_path_by,
     Params, SmallVec,
 };
 
 #[derive(Debug, Clone)]
-struct StaticChildren<T> {
+struct StaticChildren {
     indices: Vec<u8>,
-    children: Vec<Node<T>>,
+    children: Vec<Node>,
 }
 
 #[derive(Debug, Clone)]
-struct Endpoint<T> {
-    value: T,
+struct Endpoint {
     param_mapping: Vec<Vec<u8>>,
+    id: RouteId,
 }
 
 #[derive(Debug, Clone)]
-pub struct ParamNode<T> {
-    endpoint: Option<Endpoint<T>>,
-    child: Option<Box<Node<T>>>,
+struct Entry<T> {
+    // The route as inserted.
+    route: Vec<u8>,
+    value: T,
 }
 
 #[derive(Debug, Clone)]
-pub struct CatchAllNode<T> {
-    endpoint: Endpoint<T>,
+pub struct ParamNode {
+    endpoint: Option<Endpoint>,
+    child: Option<Box<Node>>,
 }
 
 #[derive(Debug, Clone)]
-pub struct Node<T> {
-    endpoint: Option<Endpoint<T>>,
+pub struct Node {
+    endpoint: Option<Endpoint>,
     matching: Vec<u8>,
-    static_children: StaticChildren<T>,
-    param_child: Option<ParamNode<T>>,
-    catch_all_child: Option<CatchAllNode<T>>,
-    // regices: Vec<>
+    static_children: StaticChildren,
+    param_child: Option<ParamNode>,
+    // Tried in insertion order after `param_child`.
+    constrained_children: Vec<(Constraint, ParamNode)>,
+    // `:name+`, tried after constrained children.
+    one_or_more_child: Option<ParamNode>,
+    // The child holds the static text after a catch all in the middle of a route.
+    catch_all_child: Option<ParamNode>,
+}
+
+/// Which param node of a node a param segment is inserted to.
+#[derive(Clone, Copy)]
+enum ParamSlot {
+    Param,
+    // Holds the index of the constrained child.
+    Constrained(usize),
+    OneOrMore,
+    CatchAll,
+}
+
+/// Result of `Tree::lookup`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Lookup<'n, 'p, T> {
+    Found(&'n T, Params<'n, 'p>),
+    /// No route matches, but the path with a trailing slash added or removed does.
+    TrailingSlashRedirect,
+    NotFound,
+}
+
+/// A matched route, returned by `Tree::at_match`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Match<'n, 'p, T> {
+    pub value: &'n T,
+    pub params: Params<'n, 'p>,
+    /// The route as inserted, like `/users/:id`.
+    pub route: &'n [u8],
+    pub id: RouteId,
+}
+
+/// A route shared by several inserts, returned by `Tree::get_or_insert_with`.
+pub(crate) struct Shared<'a, T> {
+    pub value: &'a mut T,
+    /// The route the value was inserted with.
+    pub route: &'a [u8],
+    /// Param names of the inserted route if they differ from the ones of `route`.
+    pub names: Option<Vec<Vec<u8>>>,
+}
+
+/// Id of an inserted route, given out in insertion order from 0 so it can index a `Vec`.
+/// Ids are not reused after the route is removed, unless no route inserted after it is left.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RouteId(usize);
+
+impl RouteId {
+    #[inline(always)]
+    pub const fn index(self) -> usize {
+        self.0
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct Tree<T> {
-    static_children: StaticChildren<T>,
+    // Matches nothing itself, so routes may start with any segment. It is never compacted.
+    // Endpoints only hold the route id, routes and values are kept in `entries`.
+    root: Node,
+    // Set by `with_percent_decoding`.
+    decoding: Option<EncodedSlash>,
+    // Set by `with_separator`.
+    separator: u8,
+    // Routes and values by id, `None` once removed. Trailing `None`s are dropped, so the ids
+    // of the routes removed last may be given out again.
+    entries: Vec<Option<Entry<T>>>,
 }
 
 impl<T> Default for Tree<T> {
     #[inline(always)]
     fn default() -> Self {
-        Self {
-            static_children: StaticChildren::new(),
-        }
+        Self::new()
     }
 }
 
-impl<T> Endpoint<T> {
+impl Endpoint {
     fn remapping<'n, 'p>(
         &'n self,
         mut input: SmallVec<(&'n [u8], &'p [u8])>,
@@ -65,7 +131,7 @@ impl<T> Endpoint<T> {
     }
 }
 
-impl<T> StaticChildren<T> {
+impl StaticChildren {
     #[inline(always)]
     const fn new() -> Self {
         Self {
@@ -75,7 +141,7 @@ impl<T> StaticChildren<T> {
     }
 
     #[inline(always)]
-    fn get(&self, byte: u8) -> Option<&Node<T>> {
+    fn get(&self, byte: u8) -> Option<&Node> {
         let idx = memchr::memchr(byte, &self.indices)?;
         Some(unsafe { self.children.get_unchecked(idx) })
     }
@@ -83,7 +149,7 @@ impl<T> StaticChildren<T> {
     /// # Safety
     /// `segment_path` can not be empty.
     #[inline(always)]
-    unsafe fn get_mut_or_insert_unchecked(&mut self, segment_path: &[u8]) -> &mut Node<T> {
+    unsafe fn get_mut_or_insert_unchecked(&mut self, segment_path: &[u8]) -> &mut Node {
         let first = *segment_path.first().unwrap_unchecked();
         if let Some(idx) = memchr::memchr(first, &self.indices) {
             return self.children.get_unchecked_mut(idx);
@@ -91,10 +157,63 @@ impl<T> StaticChildren<T> {
         self.insert_unchecked(first, Node::new(segment_path))
     }
 
+    /// Walk the children whose first byte equals the first byte of `path` ignoring ASCII case,
+    /// the one with the same case first.
+    /// # Safety
+    /// `path` can not be empty.
+    #[inline]
+    unsafe fn find_case_insensitive_unchecked(
+        &self,
+        path: &[u8],
+        sep: u8,
+        out: &mut Vec<u8>,
+        fix_tsr: bool,
+    ) -> bool {
+        let first = *path.first().unwrap_unchecked();
+        let swapped = match first {
+            b'a'..=b'z' => first.to_ascii_uppercase(),
+            _ => first.to_ascii_lowercase(),
+        };
+        self.get(first)
+            .is_some_and(|node| node.find_case_insensitive(path, sep, out, fix_tsr))
+            || (first != swapped
+                && self
+                    .get(swapped)
+                    .is_some_and(|node| node.find_case_insensitive(path, sep, out, fix_tsr)))
+    }
+
+    /// Find the endpoint matching `path` and `rest` in the child selected by the first byte of
+    /// `path`.
+    /// # Safety
+    /// `path` can not be empty.
+    #[inline]
+    unsafe fn find_unchecked(&self, path: &[u8], rest: &[Segment]) -> Option<&Endpoint> {
+        self.get(*path.first().unwrap_unchecked())?.find(path, rest)
+    }
+
+    /// Remove the endpoint matching `path` and `rest` from the child selected by the first byte
+    /// of `path`, and drop that child if nothing is left in it.
+    /// # Safety
+    /// `path` can not be empty.
+    #[inline]
+    unsafe fn remove_unchecked(&mut self, path: &[u8], rest: &[Segment]) -> Option<Endpoint> {
+        let first = *path.first().unwrap_unchecked();
+        let idx = memchr::memchr(first, &self.indices)?;
+        let child = self.children.get_unchecked_mut(idx);
+        let endpoint = child.remove(path, rest)?;
+        if child.is_empty() {
+            self.indices.swap_remove(idx);
+            self.children.swap_remove(idx);
+        } else {
+            child.compact();
+        }
+        Some(endpoint)
+    }
+
     /// # Safety
     /// Must make sure that `byte` is not in `self.indices`.
     #[inline(always)]
-    unsafe fn insert_unchecked(&mut self, byte: u8, node: Node<T>) -> &mut Node<T> {
+    unsafe fn insert_unchecked(&mut self, byte: u8, node: Node) -> &mut Node {
         debug_assert_eq!(self.indices.len(), self.children.len());
         debug_assert!(!self.indices.contains(&byte));
         self.indices.push(byte);
@@ -103,7 +222,94 @@ impl<T> StaticChildren<T> {
     }
 }
 
-impl<T> Node<T> {
+impl ParamNode {
+    #[inline(always)]
+    const fn new() -> Self {
+        Self {
+            endpoint: None,
+            child: None,
+        }
+    }
+
+    #[inline(always)]
+    fn is_empty(&self) -> bool {
+        self.endpoint.is_none() && self.child.is_none()
+    }
+
+    /// Whether the param would match after adding or removing a trailing separator, given the
+    /// rest of the path after the param value.
+    #[inline]
+    fn tsr(&self, rest: &[u8], sep: u8) -> bool {
+        match rest {
+            [b] if *b == sep => self.endpoint.is_some(),
+            [] => self
+                .child
+                .as_ref()
+                .is_some_and(|child| child.matching == [sep] && child.matches_empty()),
+            _ => false,
+        }
+    }
+
+    /// Find the next end of a param value at the start of `path`, `prev` is the end tried last.
+    /// Values followed by a byte the static text after the param can start with are tried
+    /// first, longest first, then the whole segment.
+    #[inline]
+    fn next_end(&self, path: &[u8], sep: u8, prev: Option<usize>) -> Option<usize> {
+        let segment_end = memchr::memchr(sep, path).unwrap_or(path.len());
+        let below = match prev {
+            None => segment_end,
+            Some(prev) if prev == segment_end => return None,
+            Some(prev) => prev,
+        };
+        let inner = self.child.as_ref().and_then(|child| {
+            let starts = |b: &u8| match child.matching.first() {
+                Some(first) => first == b,
+                None => child.static_children.indices.contains(b),
+            };
+            // Values are not empty unless they are the whole segment.
+            path.get(1..below)?
+                .iter()
+                .rposition(starts)
+                .map(|idx| idx + 1)
+        });
+        Some(inner.unwrap_or(segment_end))
+    }
+
+    /// Like `next_end`, but for catch alls, whose values end at any separator followed by the
+    /// static text after the catch all. The longest value is tried first, and the whole path
+    /// last.
+    #[inline]
+    fn next_catch_all_end(&self, path: &[u8], sep: u8, prev: Option<usize>) -> Option<usize> {
+        let below = match prev {
+            None => path.len(),
+            Some(prev) if prev == path.len() => return None,
+            Some(prev) => prev,
+        };
+        let inner = self
+            .child
+            .as_ref()
+            .and_then(|_| memchr::memrchr(sep, path.get(1..below)?).map(|idx| idx + 1));
+        match inner {
+            Some(end) => Some(end),
+            None => self.endpoint.as_ref().map(|_| path.len()),
+        }
+    }
+}
+
+impl Node {
+    #[inline(always)]
+    const fn root() -> Self {
+        Self {
+            endpoint: None,
+            matching: Vec::new(),
+            static_children: StaticChildren::new(),
+            param_child: None,
+            constrained_children: Vec::new(),
+            one_or_more_child: None,
+            catch_all_child: None,
+        }
+    }
+
     #[inline(always)]
     fn new(path: &[u8]) -> Self {
         Self {
@@ -111,46 +317,482 @@ impl<T> Node<T> {
             matching: path.to_vec(),
             static_children: StaticChildren::new(),
             param_child: None,
+            constrained_children: Vec::new(),
+            one_or_more_child: None,
             catch_all_child: None,
         }
     }
 
-    pub fn at<'n, 'p>(&'n self, path: &'p [u8]) -> Option<(&'n T, Params<'n, 'p>)> {
-        // Skipped saves the parent node's information.
-        enum Skipped<'n, 'p, T> {
-            Param {
-                p_path: &'p [u8],
-                p_node: &'n Node<T>,
-                valid_p: usize,
-            },
-            CatchAll {
-                f_path: &'p [u8],
-                f_node: &'n Node<T>,
-                valid_p: usize,
+    #[inline(always)]
+    fn is_empty(&self) -> bool {
+        self.endpoint.is_none()
+            && self.static_children.children.is_empty()
+            && self.param_child.is_none()
+            && self.constrained_children.is_empty()
+            && self.one_or_more_child.is_none()
+            && self.catch_all_child.is_none()
+    }
+
+    /// Merge the node with its only static child if the node itself holds nothing, so the tree
+    /// keeps the shape it would have if the removed route had never been inserted.
+    fn compact(&mut self) {
+        if self.endpoint.is_some()
+            || self.param_child.is_some()
+            || !self.constrained_children.is_empty()
+            || self.one_or_more_child.is_some()
+            || self.catch_all_child.is_some()
+            || self.static_children.children.len() != 1
+        {
+            return;
+        }
+        let child = unsafe { self.static_children.children.pop().unwrap_unchecked() };
+        self.static_children.indices.clear();
+        self.matching.extend_from_slice(&child.matching);
+        self.endpoint = child.endpoint;
+        self.static_children = child.static_children;
+        self.param_child = child.param_child;
+        self.constrained_children = child.constrained_children;
+        self.one_or_more_child = child.one_or_more_child;
+        self.catch_all_child = child.catch_all_child;
+    }
+
+    /// Get the param node selected during insertion.
+    #[inline]
+    fn param_slot(&mut self, slot: ParamSlot) -> &mut ParamNode {
+        match slot {
+            ParamSlot::Param => self.param_child.as_mut().unwrap(),
+            ParamSlot::Constrained(idx) => &mut self.constrained_children[idx].1,
+            ParamSlot::OneOrMore => self.one_or_more_child.as_mut().unwrap(),
+            ParamSlot::CatchAll => self.catch_all_child.as_mut().unwrap(),
+        }
+    }
+
+    /// Get the param node a non-static segment leads to.
+    #[inline]
+    fn param_node(&self, seg: &Segment) -> Option<&ParamNode> {
+        match seg {
+            Segment::Param(_) => self.param_child.as_ref(),
+            Segment::OneOrMore(_) => self.one_or_more_child.as_ref(),
+            Segment::CatchAll(_) => self.catch_all_child.as_ref(),
+            Segment::Constrained(_, text) => {
+                let constraint = Constraint::parse(text)?;
+                self.constrained_children
+                    .iter()
+                    .find(|(c, _)| *c == constraint)
+                    .map(|(_, pn)| pn)
+            }
+            _ => None,
+        }
+    }
+
+    /// Get the param node a non-static segment leads to.
+    #[inline]
+    fn param_node_mut(&mut self, seg: &Segment) -> Option<&mut ParamNode> {
+        match seg {
+            Segment::Param(_) => self.param_child.as_mut(),
+            Segment::OneOrMore(_) => self.one_or_more_child.as_mut(),
+            Segment::CatchAll(_) => self.catch_all_child.as_mut(),
+            Segment::Constrained(_, text) => {
+                let constraint = Constraint::parse(text)?;
+                self.constrained_children
+                    .iter_mut()
+                    .find(|(c, _)| *c == constraint)
+                    .map(|(_, pn)| pn)
+            }
+            _ => None,
+        }
+    }
+
+    /// Find the endpoint described by `path` (static bytes starting at this node) and the
+    /// following segments.
+    fn find(&self, path: &[u8], rest: &[Segment]) -> Option<&Endpoint> {
+        let tail = path.strip_prefix(self.matching.as_slice())?;
+        if !tail.is_empty() {
+            return unsafe { self.static_children.find_unchecked(tail, rest) };
+        }
+        match rest {
+            [] => self.endpoint.as_ref(),
+            [Segment::Static(p), rest @ ..] => unsafe {
+                self.static_children.find_unchecked(p, rest)
             },
+            [seg, rest @ ..] => {
+                let pn = self.param_node(seg)?;
+                match rest {
+                    [] => pn.endpoint.as_ref(),
+                    [Segment::Static(p), rest @ ..] => pn.child.as_ref()?.find(p, rest),
+                    _ => None,
+                }
+            }
         }
+    }
 
-        let mut node = self;
-        let mut params: SmallVec<(&[u8], &[u8])> = SmallVec::new();
-        let mut path = path;
+    /// Remove the endpoint described by `path` (static bytes starting at this node) and the
+    /// following segments.
+    fn remove(&mut self, path: &[u8], rest: &[Segment]) -> Option<Endpoint> {
+        let tail = path.strip_prefix(self.matching.as_slice())?;
+        let endpoint = if !tail.is_empty() {
+            unsafe { self.static_children.remove_unchecked(tail, rest)? }
+        } else {
+            match rest {
+                [] => self.endpoint.take()?,
+                [Segment::Static(p), rest @ ..] => unsafe {
+                    self.static_children.remove_unchecked(p, rest)?
+                },
+                [seg, rest @ ..] => {
+                    let pn = self.param_node_mut(seg)?;
+                    match rest {
+                        [] => pn.endpoint.take()?,
+                        [Segment::Static(p), rest @ ..] => {
+                            let pcc = pn.child.as_mut()?;
+                            let endpoint = pcc.remove(p, rest)?;
+                            if pcc.is_empty() {
+                                pn.child = None;
+                            } else {
+                                pcc.compact();
+                            }
+                            endpoint
+                        }
+                        _ => return None,
+                    }
+                }
+            }
+        };
+        if self.param_child.as_ref().is_some_and(ParamNode::is_empty) {
+            self.param_child = None;
+        }
+        self.constrained_children.retain(|(_, pn)| !pn.is_empty());
+        if self
+            .one_or_more_child
+            .as_ref()
+            .is_some_and(ParamNode::is_empty)
+        {
+            self.one_or_more_child = None;
+        }
+        if self
+            .catch_all_child
+            .as_ref()
+            .is_some_and(ParamNode::is_empty)
+        {
+            self.catch_all_child = None;
+        }
+        Some(endpoint)
+    }
+
+    /// Push the endpoints of the node and the nodes below it to `out`: static children sorted by
+    /// their first byte, then param children in matching priority order.
+    fn endpoints<'a>(&'a self, out: &mut Vec<&'a Endpoint>) {
+        out.extend(&self.endpoint);
+        let mut children: SmallVec<&Node> = self.static_children.children.iter().collect();
+        children.sort_unstable_by_key(|child| child.matching.first().copied());
+        for child in children {
+            child.endpoints(out);
+        }
+        let mut constrained: SmallVec<_> = self.constrained_children.iter().collect();
+        constrained.sort_unstable_by(|(a, _), (b, _)| a.sort_key().cmp(&b.sort_key()));
+        let params = self
+            .param_child
+            .iter()
+            .chain(constrained.into_iter().map(|(_, pn)| pn))
+            .chain(&self.one_or_more_child)
+            .chain(&self.catch_all_child);
+        for pn in params {
+            out.extend(&pn.endpoint);
+            if let Some(child) = &pn.child {
+                child.endpoints(out);
+            }
+        }
+    }
+
+    /// Like `endpoints`, but taking them.
+    fn into_endpoints(self, out: &mut Vec<Endpoint>) {
+        out.extend(self.endpoint);
+        let mut children = self.static_children.children;
+        children.sort_unstable_by_key(|child| child.matching.first().copied());
+        for child in children {
+            child.into_endpoints(out);
+        }
+        let mut constrained = self.constrained_children;
+        constrained.sort_unstable_by(|(a, _), (b, _)| a.sort_key().cmp(&b.sort_key()));
+        let params = self
+            .param_child
+            .into_iter()
+            .chain(constrained.into_iter().map(|(_, pn)| pn))
+            .chain(self.one_or_more_child)
+            .chain(self.catch_all_child);
+        for pn in params {
+            out.extend(pn.endpoint);
+            if let Some(child) = pn.child {
+                child.into_endpoints(out);
+            }
+        }
+    }
+
+    /// Whether the node matches when nothing is left in the path.
+    #[inline(always)]
+    fn matches_empty(&self) -> bool {
+        self.endpoint.is_some()
+            || self
+                .catch_all_child
+                .as_ref()
+                .is_some_and(|c| c.endpoint.is_some())
+    }
+
+    /// Whether appending a separator to a path ending at this node would match.
+    #[inline]
+    fn tsr_with_slash(&self, sep: u8) -> bool {
+        self.static_children
+            .get(sep)
+            .is_some_and(|child| child.matching == [sep] && child.matches_empty())
+    }
+
+    /// Match the path ignoring ASCII case of static parts, and push the path with the static
+    /// parts as registered to `out`. Param values are kept as is.
+    /// With `fix_tsr`, a trailing separator may also be added or removed.
+    fn find_case_insensitive(
+        &self,
+        path: &[u8],
+        sep: u8,
+        out: &mut Vec<u8>,
+        fix_tsr: bool,
+    ) -> bool {
+        let out_len = out.len();
+        let matching = self.matching.as_slice();
+        if path.len() < matching.len() {
+            // the path may only miss the trailing separator
+            if fix_tsr
+                && path.len() + 1 == matching.len()
+                && matching.ends_with(&[sep])
+                && matching[..path.len()].eq_ignore_ascii_case(path)
+                && self.matches_empty()
+            {
+                out.extend_from_slice(matching);
+                return true;
+            }
+            return false;
+        }
+        let (prefix, rest) = path.split_at(matching.len());
+        if !prefix.eq_ignore_ascii_case(matching) {
+            return false;
+        }
+        out.extend_from_slice(matching);
+
+        if rest.is_empty() {
+            if self.matches_empty() {
+                return true;
+            }
+            if fix_tsr && self.tsr_with_slash(sep) {
+                out.push(sep);
+                return true;
+            }
+            out.truncate(out_len);
+            return false;
+        }
+        if unsafe {
+            self.static_children
+                .find_case_insensitive_unchecked(rest, sep, out, fix_tsr)
+        } {
+            return true;
+        }
+
+        let params = self.param_child.iter().map(|pn| (None, pn)).chain(
+            self.constrained_children
+                .iter()
+                .map(|(c, pn)| (Some(c), pn)),
+        );
+        for (constraint, pn) in params {
+            let mut end = None;
+            while let Some(param_end) = pn.next_end(rest, sep, end) {
+                end = Some(param_end);
+                let (param_data, new_rest) = rest.split_at(param_end);
+                if constraint.is_some_and(|c| !c.is_match(param_data)) {
+                    continue;
+                }
+                let param_len = out.len();
+                out.extend_from_slice(param_data);
+                if new_rest.is_empty() {
+                    if pn.endpoint.is_some() {
+                        return true;
+                    }
+                } else if pn
+                    .child
+                    .as_ref()
+                    .is_some_and(|child| child.find_case_insensitive(new_rest, sep, out, fix_tsr))
+                {
+                    return true;
+                }
+                if fix_tsr && pn.tsr(new_rest, sep) {
+                    if new_rest.is_empty() {
+                        out.push(sep);
+                    }
+                    return true;
+                }
+                out.truncate(param_len);
+            }
+        }
+
+        let catch_alls = self
+            .one_or_more_child
+            .iter()
+            .map(|c| (true, c))
+            .chain(self.catch_all_child.iter().map(|c| (false, c)));
+        for (one_or_more, catch_all) in catch_alls {
+            let mut end = None;
+            while let Some(value_end) = catch_all.next_catch_all_end(rest, sep, end) {
+                end = Some(value_end);
+                let (value, new_rest) = rest.split_at(value_end);
+                if one_or_more && !is_one_or_more(value, sep) {
+                    break;
+                }
+                let value_len = out.len();
+                out.extend_from_slice(value);
+                if new_rest.is_empty()
+                    || catch_all.child.as_ref().is_some_and(|child| {
+                        child.find_case_insensitive(new_rest, sep, out, fix_tsr)
+                    })
+                {
+                    return true;
+                }
+                out.truncate(value_len);
+            }
+        }
+        if fix_tsr && rest == [sep] && self.endpoint.is_some() {
+            return true;
+        }
+        out.truncate(out_len);
+        false
+    }
+}
+
+type BigVec<S> = smallvec::SmallVec<[S; 8]>;
+
+/// Where `Matches` resumes, saving the parent node's information.
+enum Skipped<'n, 'p> {
+    // `end` is the param value end tried last, see `ParamNode::next_end`.
+    Param {
+        p_path: &'p [u8],
+        p_node: &'n Node,
+        end: Option<usize>,
+        valid_p: usize,
+    },
+    // The `idx`th constrained child and the ones after it are not done yet.
+    Constrained {
+        c_path: &'p [u8],
+        c_node: &'n Node,
+        idx: usize,
+        end: Option<usize>,
+        valid_p: usize,
+    },
+    // `end` is the catch all value end tried last.
+    CatchAll {
+        f_path: &'p [u8],
+        f_node: &'n Node,
+        one_or_more: bool,
+        end: Option<usize>,
+        valid_p: usize,
+    },
+}
+
+/// Iterator over every route matching a path with its params, in matching priority order.
+/// Returned by `Tree::matches`.
+pub struct Matches<'n, 'p, T> {
+    node: &'n Node,
+    entries: &'n [Option<Entry<T>>],
+    path: &'p [u8],
+    sep: u8,
+    params: SmallVec<(&'n [u8], &'p [u8])>,
+    skipped: BigVec<Skipped<'n, 'p>>,
+    // Whether an endpoint was returned, so the next one is looked for by backtracking.
+    started: bool,
+    // The requested path if `path` is its canonical form. Params are mapped back to it before
+    // they are given out.
+    raw: Option<&'p [u8]>,
+}
+
+impl<'n, 'p, T> Matches<'n, 'p, T> {
+    #[inline]
+    fn new(tree: &'n Tree<T>, path: &'p [u8], raw: Option<&'p [u8]>) -> Self {
+        Self {
+            node: &tree.root,
+            entries: &tree.entries,
+            path,
+            sep: tree.separator,
+            params: SmallVec::new(),
+            skipped: BigVec::new(),
+            started: false,
+            raw,
+        }
+    }
+
+    #[inline(always)]
+    fn entry(&self, endpoint: &Endpoint) -> &'n Entry<T> {
+        // # Safety
+        // Entries are only removed along with their endpoints.
+        unsafe {
+            self.entries
+                .get_unchecked(endpoint.id.0)
+                .as_ref()
+                .unwrap_unchecked()
+        }
+    }
+
+    /// Params of the endpoint found last, in the requested path.
+    #[inline]
+    fn cloned_params(&self) -> Params<'n, 'p> {
+        match self.raw {
+            None => self.params.clone(),
+            Some(raw) => remap_params(&self.params, self.path, raw, self.sep),
+        }
+    }
 
-        type BigVec<S> = smallvec::SmallVec<[S; 8]>;
-        let mut skipped = BigVec::<Skipped<'n, 'p, T>>::new();
+    /// Find the next endpoint for the path with segments split by `sep`, its params are left
+    /// in `params`, which the caller clones only if it resumes.
+    /// With `TSR` enabled, `tsr` is set when the path misses but would match after adding or
+    /// removing a trailing separator.
+    fn next_match<const TSR: bool>(&mut self, tsr: &mut bool) -> Option<&'n Endpoint> {
+        let sep = self.sep;
+        let mut node = self.node;
+        let mut path = self.path;
+        let params = &mut self.params;
+        let skipped = &mut self.skipped;
+        // Endpoints before are found already, so go on with the skipped ones.
+        let mut resume = mem::replace(&mut self.started, true);
 
+        macro_rules! found {
+            ($endpoint:expr) => {{
+                *params = $endpoint.remapping(mem::take(params));
+                $endpoint
+            }};
+        }
         macro_rules! push_skipped_param {
             ($node:expr) => {
                 skipped.push(Skipped::Param {
                     p_path: path,
                     p_node: $node,
+                    end: None,
                     valid_p: params.len(),
                 });
             };
         }
-        macro_rules! push_skipped_catch_all {
+        macro_rules! push_skipped_constrained {
             ($node:expr) => {
+                if !$node.constrained_children.is_empty() {
+                    skipped.push(Skipped::Constrained {
+                        c_path: path,
+                        c_node: $node,
+                        idx: 0,
+                        end: None,
+                        valid_p: params.len(),
+                    });
+                }
+            };
+        }
+        macro_rules! push_skipped_catch_all {
+            ($node:expr, $one_or_more:expr) => {
                 skipped.push(Skipped::CatchAll {
                     f_path: path,
                     f_node: $node,
+                    one_or_more: $one_or_more,
+                    end: None,
                     valid_p: params.len(),
                 });
             };
@@ -159,97 +801,173 @@ impl<T> Node<T> {
         'main: loop {
             macro_rules! backtrack {
                 () => {{
-                    'bt: while let Some(skipped) = skipped.pop() {
-                        match skipped {
+                    'bt: while let Some(item) = skipped.pop() {
+                        let (pc, param_data, new_path) = match item {
                             Skipped::Param {
                                 p_path,
                                 p_node,
+                                end,
                                 valid_p,
                             } => {
                                 params.truncate(valid_p);
-                                let (param_data, new_path) = next_param(p_path);
-                                params.push((&[], param_data));
                                 let pc = unsafe { p_node.param_child.as_ref().unwrap_unchecked() };
-                                if new_path.is_empty() {
-                                    if let Some(ep) = &pc.endpoint {
-                                        return Some((&ep.value, ep.remapping(params)));
+                                let Some(end) = pc.next_end(p_path, sep, end) else {
+                                    continue 'bt;
+                                };
+                                // shorter values are tried if this one fails
+                                skipped.push(Skipped::Param {
+                                    p_path,
+                                    p_node,
+                                    end: Some(end),
+                                    valid_p,
+                                });
+                                let (param_data, new_path) =
+                                    unsafe { p_path.split_at_unchecked(end) };
+                                (pc, param_data, new_path)
+                            }
+                            Skipped::Constrained {
+                                c_path,
+                                c_node,
+                                idx,
+                                end,
+                                valid_p,
+                            } => {
+                                params.truncate(valid_p);
+                                let (constraint, pc) =
+                                    unsafe { c_node.constrained_children.get_unchecked(idx) };
+                                let Some(end) = pc.next_end(c_path, sep, end) else {
+                                    if idx + 1 < c_node.constrained_children.len() {
+                                        skipped.push(Skipped::Constrained {
+                                            c_path,
+                                            c_node,
+                                            idx: idx + 1,
+                                            end: None,
+                                            valid_p,
+                                        });
                                     }
                                     continue 'bt;
+                                };
+                                skipped.push(Skipped::Constrained {
+                                    c_path,
+                                    c_node,
+                                    idx,
+                                    end: Some(end),
+                                    valid_p,
+                                });
+                                let (param_data, new_path) =
+                                    unsafe { c_path.split_at_unchecked(end) };
+                                if !constraint.is_match(param_data) {
+                                    continue 'bt;
                                 }
-                                if let Some(pcc) = pc.child.as_ref() {
-                                    node = pcc;
-                                    path = new_path;
-                                    continue 'main;
-                                }
-                                continue 'bt;
+                                (pc, param_data, new_path)
                             }
                             Skipped::CatchAll {
                                 f_path,
                                 f_node,
+                                one_or_more,
+                                end,
                                 valid_p,
                             } => {
                                 params.truncate(valid_p);
-                                params.push((&[], f_path));
-                                let node =
-                                    unsafe { f_node.catch_all_child.as_ref().unwrap_unchecked() };
-                                let endpoint = &node.endpoint;
-                                return Some((&endpoint.value, endpoint.remapping(params)));
+                                let pc = if one_or_more {
+                                    &f_node.one_or_more_child
+                                } else {
+                                    &f_node.catch_all_child
+                                };
+                                let pc = unsafe { pc.as_ref().unwrap_unchecked() };
+                                let Some(end) = pc.next_catch_all_end(f_path, sep, end) else {
+                                    continue 'bt;
+                                };
+                                let (param_data, new_path) =
+                                    unsafe { f_path.split_at_unchecked(end) };
+                                // shorter values are only separators too
+                                if one_or_more && !is_one_or_more(param_data, sep) {
+                                    continue 'bt;
+                                }
+                                // shorter values are tried if this one fails
+                                skipped.push(Skipped::CatchAll {
+                                    f_path,
+                                    f_node,
+                                    one_or_more,
+                                    end: Some(end),
+                                    valid_p,
+                                });
+                                (pc, param_data, new_path)
+                            }
+                        };
+                        // enter param
+                        if TSR && pc.tsr(new_path, sep) {
+                            *tsr = true;
+                        }
+                        params.push((&[], param_data));
+                        if new_path.is_empty() {
+                            if let Some(ep) = &pc.endpoint {
+                                return Some(found!(ep));
                             }
+                            continue 'bt;
+                        }
+                        if let Some(pcc) = pc.child.as_ref() {
+                            node = pcc;
+                            path = new_path;
+                            continue 'main;
                         }
                     }
                     return None;
                 }};
             }
 
+            if mem::take(&mut resume) {
+                backtrack!();
+            }
+
             let path_len = path.len();
             let matching_len = node.matching.len();
 
             if path_len > matching_len {
                 let (prefix, rest) = unsafe { path.split_at_unchecked(matching_len) };
                 if prefix == node.matching {
+                    if TSR && rest == [sep] && node.endpoint.is_some() {
+                        *tsr = true;
+                    }
                     path = rest;
                     // # Safety
                     // Path is longer than prefix, so after split, rest is not empty.
                     let first = unsafe { *rest.first().unwrap_unchecked() };
                     let Some(new_node) = node.static_children.get(first) else {
-                        let param = match (&node.param_child, &node.catch_all_child) {
-                            (None, None) => backtrack!(),
-                            (None, Some(catch_all)) => {
+                        if let Some(catch_all) = &node.catch_all_child {
+                            if let (None, true, None, None, Some(ep)) = (
+                                &node.param_child,
+                                node.constrained_children.is_empty(),
+                                &node.one_or_more_child,
+                                &catch_all.child,
+                                &catch_all.endpoint,
+                            ) {
                                 // enter catch all
                                 params.push((&[], rest));
-                                return Some((
-                                    &catch_all.endpoint.value,
-                                    catch_all.endpoint.remapping(params),
-                                ));
-                            }
-                            (Some(param), None) => param,
-                            (Some(param), Some(_)) => {
-                                // push skipped catch all and enter param
-                                push_skipped_catch_all!(node);
-                                param
-                            }
-                        };
-                        // enter param
-                        let (param_data, new_rest) = next_param(rest);
-                        params.push((&[], param_data));
-
-                        if new_rest.is_empty() {
-                            if let Some(ep) = &param.endpoint {
-                                return Some((&ep.value, ep.remapping(params)));
+                                return Some(found!(ep));
                             }
-                        } else if let Some(pcc) = param.child.as_ref() {
-                            node = pcc;
-                            path = new_rest;
-                            continue;
+                            push_skipped_catch_all!(node, false);
+                        }
+                        // try param, constrained children, one or more and then catch all
+                        if node.one_or_more_child.is_some() {
+                            push_skipped_catch_all!(node, true);
+                        }
+                        push_skipped_constrained!(node);
+                        if node.param_child.is_some() {
+                            push_skipped_param!(node);
                         }
-                        backtrack!();
+                        backtrack!()
                     };
 
                     // we found static child now
                     // push skipped nodes and enter next node
                     if node.catch_all_child.is_some() {
-                        push_skipped_catch_all!(node);
+                        push_skipped_catch_all!(node, false);
                     }
+                    if node.one_or_more_child.is_some() {
+                        push_skipped_catch_all!(node, true);
+                    }
+                    push_skipped_constrained!(node);
                     if node.param_child.is_some() {
                         push_skipped_param!(node);
                     }
@@ -258,47 +976,137 @@ impl<T> Node<T> {
                 }
             }
             if path == node.matching {
+                // a catch all matches the empty rest after the endpoint
+                if node
+                    .catch_all_child
+                    .as_ref()
+                    .is_some_and(|c| c.endpoint.is_some())
+                {
+                    skipped.push(Skipped::CatchAll {
+                        f_path: &path[path_len..],
+                        f_node: node,
+                        one_or_more: false,
+                        end: None,
+                        valid_p: params.len(),
+                    });
+                }
                 if let Some(endpoint) = &node.endpoint {
-                    return Some((&endpoint.value, endpoint.remapping(params)));
+                    return Some(found!(endpoint));
                 }
-                if let Some(catch_all) = &node.catch_all_child {
-                    params.push((&[], b""));
-                    return Some((&catch_all.endpoint.value, catch_all.endpoint.remapping(params)));
+                if TSR && node.tsr_with_slash(sep) {
+                    *tsr = true;
                 }
                 backtrack!();
             }
+            if TSR
+                && path_len + 1 == matching_len
+                && node.matching.ends_with(&[sep])
+                && node.matching.starts_with(path)
+                && node.matches_empty()
+            {
+                *tsr = true;
+            }
             backtrack!();
         }
     }
+}
 
-    pub fn insert(&mut self, segments: SegmentsIter<'_>, value: T) -> Result<(), InsertError> {
+impl Node {
+    /// Insert the segments parsed from `route` with the id.
+    /// Param names and constraints are borrowed from `route`, which is used for errors.
+    /// With `shared`, an endpoint already inserted is returned instead of being a conflict, even
+    /// if its param names differ.
+    fn insert_with<T>(
+        &mut self,
+        route: &[u8],
+        segments: &[Segment<'_>],
+        shared: bool,
+        id: RouteId,
+        entries: &[Option<Entry<T>>],
+    ) -> Result<&mut Endpoint, InsertError> {
+        if segments.is_empty() {
+            return Err(InsertError::new(InsertErrorKind::EmptyRoute, route, 0));
+        }
         enum Status {
             Match,
             SkipMatching,
-            Param,
+            Param(ParamSlot),
+        }
+
+        // Validate the route before changing the tree.
+        let mut names: SmallVec<&[u8]> = SmallVec::new();
+        let mut constraints: SmallVec<Constraint> = SmallVec::new();
+        let mut has_catch_all = false;
+        let mut after_param = false;
+        for seg in segments {
+            let Some(name) = seg.name() else {
+                after_param = false;
+                continue;
+            };
+            let error = |kind| Err(InsertError::new(kind, route, offset_in(route, name)));
+            if after_param {
+                return error(InsertErrorKind::AdjacentParams);
+            }
+            if names.contains(&name) {
+                return error(InsertErrorKind::DuplicateParamName);
+            }
+            match seg {
+                Segment::Constrained(_, text) => match Constraint::parse(text) {
+                    Some(constraint) => constraints.push(constraint),
+                    None => {
+                        return Err(InsertError::new(
+                            InsertErrorKind::InvalidConstraint,
+                            route,
+                            offset_in(route, text),
+                        ))
+                    }
+                },
+                Segment::OneOrMore(_) | Segment::CatchAll(_) => {
+                    // Only one catch all is allowed in a route to bound backtracking.
+                    if has_catch_all {
+                        return error(InsertErrorKind::MultipleCatchAlls);
+                    }
+                    has_catch_all = true;
+                }
+                _ => (),
+            }
+            names.push(name);
+            after_param = true;
         }
 
         let mut status = Status::Match;
         let mut node = self;
-        let mut param_mapping = Vec::new();
+        let mut constraints = constraints.into_iter();
 
         macro_rules! set_endpoint {
             ($endpoint:expr) => {
-                if $endpoint.is_some() {
-                    return Err(InsertError::new());
+                if shared && $endpoint.is_some() {
+                    return Ok(unsafe { $endpoint.as_mut().unwrap_unchecked() });
                 }
-                $endpoint = Some(Endpoint {
-                    value,
-                    param_mapping,
-                })
+                if let Some(existing) = &$endpoint {
+                    // Point at the first param named differently, if any.
+                    let renamed = std::iter::zip(&existing.param_mapping, &names)
+                        .find(|(existing, name)| existing.as_slice() != **name);
+                    let offset = renamed.map_or(0, |(_, name)| offset_in(route, name));
+                    return Err(InsertError::new(
+                        InsertErrorKind::Conflict {
+                            with: entries[existing.id.0]
+                                .as_ref()
+                                .map(|entry| entry.route.clone())
+                                .unwrap_or_default(),
+                        },
+                        route,
+                        offset,
+                    ));
+                }
+                return Ok($endpoint.insert(Endpoint {
+                    param_mapping: names.iter().map(|name| name.to_vec()).collect(),
+                    id,
+                }))
             };
         }
 
-        'main: for seg in segments {
-            let seg = seg?;
-            if let Some(name) = seg.name() {
-                param_mapping.push(name.to_vec());
-            }
+        'main: for &seg in segments {
             match seg {
                 Segment::Static(mut path) => {
                     match status {
@@ -306,8 +1114,8 @@ impl<T> Node<T> {
                             node =
                                 unsafe { node.static_children.get_mut_or_insert_unchecked(path) };
                         }
-                        Status::Param => {
-                            let pcc = &mut node.param_child.as_mut().unwrap().child;
+                        Status::Param(slot) => {
+                            let pcc = &mut node.param_slot(slot).child;
                             match pcc {
                                 Some(inner) => node = inner,
                                 None => {
@@ -350,47 +1158,145 @@ impl<T> Node<T> {
                     }
                 }
                 Segment::Param(_) => {
-                    if matches!(status, Status::Param) {
-                        return Err(InsertError::new());
-                    }
                     if node.param_child.is_none() {
-                        node.param_child = Some(ParamNode {
-                            endpoint: None,
-                            child: None,
-                        });
+                        node.param_child = Some(ParamNode::new());
                     }
-                    status = Status::Param;
+                    status = Status::Param(ParamSlot::Param);
+                    continue;
+                }
+                Segment::Constrained(..) => {
+                    let constraint = unsafe { constraints.next().unwrap_unchecked() };
+                    let idx = match node
+                        .constrained_children
+                        .iter()
+                        .position(|(c, _)| *c == constraint)
+                    {
+                        Some(idx) => idx,
+                        None => {
+                            node.constrained_children
+                                .push((constraint, ParamNode::new()));
+                            node.constrained_children.len() - 1
+                        }
+                    };
+                    status = Status::Param(ParamSlot::Constrained(idx));
                     continue;
                 }
 
-                Segment::CatchAll(_) => {
-                    if node.catch_all_child.is_some() {
-                        return Err(InsertError::new());
+                Segment::OneOrMore(_) | Segment::CatchAll(_) => {
+                    let (child, slot) = match seg {
+                        Segment::OneOrMore(_) => {
+                            (&mut node.one_or_more_child, ParamSlot::OneOrMore)
+                        }
+                        _ => (&mut node.catch_all_child, ParamSlot::CatchAll),
+                    };
+                    if child.is_none() {
+                        *child = Some(ParamNode::new());
                     }
-                    node.catch_all_child = Some(CatchAllNode {
-                        endpoint: Endpoint {
-                            value,
-                            param_mapping,
-                        },
-                    });
-                    return Ok(());
+                    status = Status::Param(slot);
+                    continue;
                 }
             }
         }
         // insert endpoint
         match status {
-            Status::Match => {
-                unreachable!()
-            }
-            Status::SkipMatching => {
+            Status::Match | Status::SkipMatching => {
                 set_endpoint!(node.endpoint);
             }
-            Status::Param => {
-                let pc = node.param_child.as_mut().unwrap();
+            Status::Param(slot) => {
+                let pc = node.param_slot(slot);
                 set_endpoint!(pc.endpoint);
             }
         }
-        Ok(())
+    }
+}
+
+impl<'n, 'p, T> Iterator for Matches<'n, 'p, T> {
+    type Item = (&'n T, Params<'n, 'p>);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        let endpoint = self.next_match::<false>(&mut false)?;
+        Some((&self.entry(endpoint).value, self.cloned_params()))
+    }
+}
+
+/// Iterator over the routes of a tree with their values, returned by `Tree::iter`.
+pub struct Iter<'a, T> {
+    inner: std::vec::IntoIter<&'a Endpoint>,
+    entries: &'a [Option<Entry<T>>],
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = (&'a [u8], &'a T);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = self.entries[self.inner.next()?.id.0].as_ref()?;
+        Some((entry.route.as_slice(), &entry.value))
+    }
+}
+
+/// Iterator over the routes of a tree with mutable values, returned by `Tree::iter_mut`.
+pub struct IterMut<'a, T> {
+    inner: std::vec::IntoIter<(&'a [u8], &'a mut T)>,
+}
+
+impl<'a, T> Iterator for IterMut<'a, T> {
+    type Item = (&'a [u8], &'a mut T);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next()
+    }
+}
+
+/// Iterator taking the routes of a tree with their values, returned by `Tree::into_iter`.
+pub struct IntoIter<T> {
+    inner: std::vec::IntoIter<Endpoint>,
+    entries: Vec<Option<Entry<T>>>,
+}
+
+impl<T> Iterator for IntoIter<T> {
+    type Item = (Vec<u8>, T);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = self.entries[self.inner.next()?.id.0].take()?;
+        Some((entry.route, entry.value))
+    }
+}
+
+impl<T> IntoIterator for Tree<T> {
+    type Item = (Vec<u8>, T);
+    type IntoIter = IntoIter<T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        let mut endpoints = Vec::with_capacity(self.entries.len());
+        self.root.into_endpoints(&mut endpoints);
+        IntoIter {
+            inner: endpoints.into_iter(),
+            entries: self.entries,
+        }
+    }
+}
+
+impl<'a, T> IntoIterator for &'a Tree<T> {
+    type Item = (&'a [u8], &'a T);
+    type IntoIter = Iter<'a, T>;
+
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a mut Tree<T> {
+    type Item = (&'a [u8], &'a mut T);
+    type IntoIter = IterMut<'a, T>;
+
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
     }
 }
 
@@ -398,35 +1304,546 @@ impl<T> Tree<T> {
     #[inline(always)]
     pub const fn new() -> Self {
         Self {
-            static_children: StaticChildren::new(),
+            root: Node::root(),
+            decoding: None,
+            separator: b'/',
+            entries: Vec::new(),
         }
     }
 
+    /// Split segments by `separator` instead of `/`, e.g. `b'.'` for keys like
+    /// `metrics.:service.latency`. Params, catch alls, trailing separator redirects and
+    /// `at_clean` all work on the separator then.
+    /// Panics if routes are inserted already, or if the separator is route syntax or a param
+    /// byte.
+    #[inline(always)]
+    pub const fn with_separator(mut self, separator: u8) -> Self {
+        assert!(self.entries.is_empty(), "with_separator after insert");
+        assert!(
+            !is_param_byte(separator)
+                && !matches!(
+                    separator,
+                    b':' | b'*' | b'\\' | b'<' | b'>' | b'(' | b')' | b'?' | b'%'
+                ),
+            "with_separator with a route syntax byte"
+        );
+        self.separator = separator;
+        self
+    }
+
+    /// Enable percent-decoding: static segments match the same whether inserted or requested
+    /// encoded or not, and `at_decoded` decodes params with the given `%2F` policy.
+    /// Panics if routes are inserted already.
+    #[inline(always)]
+    pub const fn with_percent_decoding(mut self, slash: EncodedSlash) -> Self {
+        assert!(
+            self.entries.is_empty(),
+            "with_percent_decoding after insert"
+        );
+        self.decoding = Some(slash);
+        self
+    }
+
     #[inline]
     pub fn at<'n, 'p>(&'n self, path: &'p [u8]) -> Option<(&'n T, Params<'n, 'p>)> {
-        let first = *path.first()?;
-        self.static_children
-            .get(first)
-            .and_then(|node| node.at(path))
+        self.first::<false>(path, &mut false, |_, _| true)
+            .map(|(_, entry, params)| (&entry.value, params))
+    }
+
+    /// The first endpoint for the path accepted by `predicate`, see `Matches::next_match`.
+    /// With percent-decoding, the path is matched in its canonical form, like the inserted
+    /// routes, and params are mapped back to the requested path.
+    #[inline(always)]
+    fn first<'n, 'p, const TSR: bool>(
+        &'n self,
+        path: &'p [u8],
+        tsr: &mut bool,
+        mut predicate: impl FnMut(&'n T, &Params<'n, 'p>) -> bool,
+    ) -> Option<(&'n Endpoint, &'n Entry<T>, Params<'n, 'p>)> {
+        let Some(canonical) = self.canonicalize(path) else {
+            let mut matches = Matches::new(self, path, None);
+            // Params are only cloned if the iterator is used, so they are checked in place here.
+            while let Some(endpoint) = matches.next_match::<TSR>(tsr) {
+                let entry = matches.entry(endpoint);
+                if predicate(&entry.value, &matches.params) {
+                    return Some((endpoint, entry, mem::take(&mut matches.params)));
+                }
+            }
+            return None;
+        };
+        let mut matches = Matches::new(self, &canonical, None);
+        while let Some(endpoint) = matches.next_match::<TSR>(tsr) {
+            let entry = matches.entry(endpoint);
+            let params = remap_params(&matches.params, &canonical, path, self.separator);
+            if predicate(&entry.value, &params) {
+                return Some((endpoint, entry, params));
+            }
+        }
+        None
+    }
+
+    /// The canonical form of the path with percent-decoding, if it differs.
+    #[inline(always)]
+    fn canonicalize(&self, path: &[u8]) -> Option<Vec<u8>> {
+        self.decoding?;
+        match canonicalize(path, self.separator) {
+            Cow::Owned(canonical) => Some(canonical),
+            Cow::Borrowed(_) => None,
+        }
+    }
+
+    /// Like `at`, but also gives the matched route, e.g. for metrics labels.
+    /// Routes from `insert_optional` are given as expanded.
+    #[inline]
+    pub fn at_match<'n, 'p>(&'n self, path: &'p [u8]) -> Option<Match<'n, 'p, T>> {
+        self.first::<false>(path, &mut false, |_, _| true)
+            .map(|(ep, entry, params)| Match {
+                value: &entry.value,
+                params,
+                route: &entry.route,
+                id: ep.id,
+            })
+    }
+
+    /// Every route matching the path with its params, in the order `at` tries them, so a
+    /// handler can decline and fall through to the next one. The first one is what `at` gives.
+    /// A route may come more than once if its params can split the path in more than one way.
+    /// With percent-decoding, the path is canonicalized into `buf` when it has escapes.
+    #[inline]
+    pub fn matches<'n, 'p>(&'n self, path: &'p [u8], buf: &'p mut Vec<u8>) -> Matches<'n, 'p, T> {
+        match self.canonicalize(path) {
+            Some(canonical) => {
+                *buf = canonical;
+                Matches::new(self, buf, Some(path))
+            }
+            None => Matches::new(self, path, None),
+        }
+    }
+
+    /// Like `matches`, for trees without percent-decoding.
+    #[inline]
+    pub(crate) fn matches_undecoded<'n, 'p>(&'n self, path: &'p [u8]) -> Matches<'n, 'p, T> {
+        debug_assert!(self.decoding.is_none());
+        Matches::new(self, path, None)
+    }
+
+    /// Like `at`, but routes are only taken if `predicate` accepts their value and params, e.g.
+    /// to check a version header. A rejected route makes the match go on to the next best one.
+    #[inline]
+    pub fn at_with<'n, 'p>(
+        &'n self,
+        path: &'p [u8],
+        predicate: impl FnMut(&'n T, &Params<'n, 'p>) -> bool,
+    ) -> Option<(&'n T, Params<'n, 'p>)> {
+        self.first::<false>(path, &mut false, predicate)
+            .map(|(_, entry, params)| (&entry.value, params))
+    }
+
+    /// Match the path cleaned by [`clean_path`](crate::clean_path), e.g. `/a//b/./c/../d` is
+    /// matched as `/a/b/d`. With `with_separator`, the path is cleaned by the separator instead.
+    /// With percent-decoding, escaped dot segments like `%2e%2e` are resolved too, and other
+    /// escapes are kept.
+    /// The returned bool is true if the path is not clean, the caller may redirect to the cleaned
+    /// path left in `buf` then.
+    pub fn at_clean<'n, 'p>(
+        &'n self,
+        path: &'p [u8],
+        buf: &'p mut Vec<u8>,
+    ) -> Option<(&'n T, Params<'n, 'p>, bool)> {
+        match clean_path_by(path, self.separator, self.decoding.is_some()) {
+            Cow::Borrowed(path) => self.at(path).map(|(value, params)| (value, params, false)),
+            Cow::Owned(cleaned) => {
+                *buf = cleaned;
+                let path: &'p Vec<u8> = buf;
+                self.at(path).map(|(value, params)| (value, params, true))
+            }
+        }
+    }
+
+    /// Like `at`, but tells whether the path would match after adding or removing a trailing
+    /// slash when it misses, so the caller can redirect instead of answering not found.
+    #[inline]
+    pub fn lookup<'n, 'p>(&'n self, path: &'p [u8]) -> Lookup<'n, 'p, T> {
+        let mut tsr = false;
+        match self.first::<true>(path, &mut tsr, |_, _| true) {
+            Some((_, entry, params)) => Lookup::Found(&entry.value, params),
+            None if tsr => Lookup::TrailingSlashRedirect,
+            None => Lookup::NotFound,
+        }
+    }
+
+    /// Match the path with params percent-decoded, borrowed when they have no escape.
+    /// With `with_percent_decoding`, the path is canonicalized into `buf` first when it has
+    /// escapes, and `%2F` (or the escaped separator) is handled as configured there; otherwise
+    /// params containing it are rejected.
+    pub fn at_decoded<'n, 'p>(
+        &'n self,
+        path: &'p [u8],
+        buf: &'p mut Vec<u8>,
+    ) -> Option<(&'n T, ParamsDecoded<'n, 'p>)> {
+        let path: &'p [u8] = match self.canonicalize(path) {
+            Some(canonical) => {
+                *buf = canonical;
+                buf
+            }
+            None => path,
+        };
+        let slash = self.decoding.unwrap_or_default();
+        let (value, params) = self.at(path)?;
+        let params = params
+            .into_iter()
+            .map(|(k, v)| decode_param(v, slash, self.separator).map(|v| (k, v)))
+            .collect::<Option<_>>()?;
+        Some((value, params))
     }
 
+    /// Optional parts like `/docs/:version?` are not expanded here, as every route they expand
+    /// into needs its own copy of the value; use `insert_optional` for them.
     #[inline]
     pub fn insert(&mut self, route: &[u8], val: T) -> Result<(), InsertError> {
-        let Some(Ok(Segment::Static(p))) = SegmentsIter::new(route).next() else {
-            // The first segment must be static
-            return Err(InsertError::new());
+        self.insert_id(route, val).map(|_| ())
+    }
+
+    /// Like `insert`, but gives the id of the route.
+    pub fn insert_id(&mut self, route: &[u8], val: T) -> Result<RouteId, InsertError> {
+        let id = RouteId(self.entries.len());
+        let decoding = self.decoding.is_some();
+        with_segments(route, self.separator, decoding, |segments| {
+            self.root
+                .insert_with(route, segments, false, id, &self.entries)
+        })??;
+        self.entries.push(Some(Entry {
+            route: route.to_vec(),
+            value: val,
+        }));
+        Ok(id)
+    }
+
+    /// Get the value of the route, inserting the one made by `default` if it is not in the tree
+    /// yet. The route may name its params differently than when it was inserted.
+    pub(crate) fn get_or_insert_with(
+        &mut self,
+        route: &[u8],
+        default: impl FnOnce() -> T,
+    ) -> Result<Shared<'_, T>, InsertError> {
+        let id = RouteId(self.entries.len());
+        let decoding = self.decoding.is_some();
+        let (endpoint, names) = with_segments(route, self.separator, decoding, |segments| {
+            let endpoint = self
+                .root
+                .insert_with(route, segments, true, id, &self.entries)?;
+            let names = segments.iter().filter_map(Segment::name);
+            let renamed = match names
+                .clone()
+                .eq(endpoint.param_mapping.iter().map(Vec::as_slice))
+            {
+                true => None,
+                false => Some(names.map(<[u8]>::to_vec).collect()),
+            };
+            Ok::<_, InsertError>((endpoint, renamed))
+        })??;
+        if endpoint.id == id {
+            self.entries.push(Some(Entry {
+                route: route.to_vec(),
+                value: default(),
+            }));
+        }
+        // # Safety
+        // Entries are only removed along with their endpoints.
+        let entry = unsafe {
+            self.entries
+                .get_unchecked_mut(endpoint.id.0)
+                .as_mut()
+                .unwrap_unchecked()
+        };
+        Ok(Shared {
+            value: &mut entry.value,
+            route: &entry.route,
+            names,
+        })
+    }
+
+    /// Every route with its value. The route is the one given to `insert` and kept with the
+    /// value, not rebuilt from the tree, so it has the param names and escapes as written.
+    /// Static routes come sorted by their bytes at each node, then routes with params in matching
+    /// priority order, constrained params sorted by their constraint, so the order is the same
+    /// for trees with the same routes.
+    pub fn iter(&self) -> Iter<'_, T> {
+        let mut endpoints = Vec::with_capacity(self.entries.len());
+        self.root.endpoints(&mut endpoints);
+        Iter {
+            inner: endpoints.into_iter(),
+            entries: &self.entries,
+        }
+    }
+
+    /// Like `iter`, but the values are mutable.
+    pub fn iter_mut(&mut self) -> IterMut<'_, T> {
+        let mut endpoints = Vec::with_capacity(self.entries.len());
+        self.root.endpoints(&mut endpoints);
+        let mut entries: Vec<_> = self.entries.iter_mut().map(Option::as_mut).collect();
+        let inner: Vec<_> = endpoints
+            .into_iter()
+            .filter_map(|ep| {
+                let entry = entries[ep.id.0].take()?;
+                Some((entry.route.as_slice(), &mut entry.value))
+            })
+            .collect();
+        IterMut {
+            inner: inner.into_iter(),
+        }
+    }
+
+    /// The value of the route with the id, if it is not removed.
+    #[inline]
+    pub fn get_by_id(&self, id: RouteId) -> Option<&T> {
+        self.entries.get(id.0)?.as_ref().map(|entry| &entry.value)
+    }
+
+    /// Like `get_by_id`, but mutable.
+    #[inline]
+    pub fn get_by_id_mut(&mut self, id: RouteId) -> Option<&mut T> {
+        self.entries
+            .get_mut(id.0)?
+            .as_mut()
+            .map(|entry| &mut entry.value)
+    }
+
+    /// The value of the route, which is looked up by its pattern rather than matched like a
+    /// path, so `/users/:id` gives the value of `/users/:id` only.
+    /// Params with different names at the same position are treated as the same route, just
+    /// like `insert` does.
+    #[inline]
+    pub fn get(&self, route: &[u8]) -> Option<&T> {
+        self.get_by_id(self.find(route)?.id)
+    }
+
+    /// Like `get`, but mutable, e.g. to replace a handler.
+    #[inline]
+    pub fn get_mut(&mut self, route: &[u8]) -> Option<&mut T> {
+        let id = self.find(route)?.id;
+        self.get_by_id_mut(id)
+    }
+
+    /// Whether the route is inserted, see `get`.
+    #[inline]
+    pub fn contains_route(&self, route: &[u8]) -> bool {
+        self.find(route).is_some()
+    }
+
+    /// The route with the id as inserted, if it is not removed.
+    #[inline]
+    pub fn route_pattern(&self, id: RouteId) -> Option<&[u8]> {
+        self.entries
+            .get(id.0)?
+            .as_ref()
+            .map(|entry| entry.route.as_slice())
+    }
+
+    /// Insert a route with optional parts, which is expanded into every route it describes,
+    /// all sharing the value.
+    /// - `/docs/:version?` is inserted as `/docs/:version` and `/docs`.
+    /// - `/api(/v1)?/users` is inserted as `/api/v1/users` and `/api/users`.
+    ///
+    /// Nothing is inserted if any of the expanded routes is invalid or conflicts, and errors
+    /// point into `route`. A route may expand into at most 256 routes, i.e. 8 optional parts.
+    #[inline]
+    pub fn insert_optional(&mut self, route: &[u8], val: T) -> Result<(), InsertError>
+    where
+        T: Clone,
+    {
+        self.insert_optional_id(route, val).map(|_| ())
+    }
+
+    /// Like `insert_optional`, but returns the ids of the expanded routes, longest first.
+    pub fn insert_optional_id(&mut self, route: &[u8], val: T) -> Result<Vec<RouteId>, InsertError>
+    where
+        T: Clone,
+    {
+        let expanded = expand_optional(route, self.separator)?;
+        let Some((last, rest)) = expanded.split_last() else {
+            return Ok(Vec::new());
+        };
+        let mut ids = Vec::with_capacity(expanded.len());
+        let mut result = Ok(());
+        for alt in rest {
+            match self.insert_id(&alt.route, val.clone()) {
+                Ok(id) => ids.push(id),
+                Err(err) => {
+                    result = Err(err);
+                    break;
+                }
+            }
+        }
+        if result.is_ok() {
+            match self.insert_id(&last.route, val) {
+                Ok(id) => ids.push(id),
+                Err(err) => result = Err(err),
+            }
+        }
+        if let Err(err) = result {
+            // Roll back, which gives out the ids of the removed routes again.
+            let inserted = ids.len();
+            for alt in &expanded[..inserted] {
+                self.remove(&alt.route);
+            }
+            let offset = expanded[inserted].original_offset(err.offset(), route.len());
+            return Err(InsertError::new(err.kind().clone(), route, offset));
+        }
+        Ok(ids)
+    }
+
+    fn find(&self, route: &[u8]) -> Option<&Endpoint> {
+        with_segments(route, self.separator, self.decoding.is_some(), |segments| {
+            self.root.find(&[], segments)
+        })
+        .ok()?
+    }
+
+    /// Find the path as registered for a path whose static parts may differ in ASCII case, e.g.
+    /// `/Users/42` gives `/users/42` for route `/users/:id`, so the caller can redirect to it.
+    /// Param values are kept as sent, and so are escapes with percent-decoding.
+    /// With `fix_trailing_slash`, a path which only matches after adding or removing a trailing
+    /// slash is also corrected.
+    pub fn find_case_insensitive_path(
+        &self,
+        path: &[u8],
+        fix_trailing_slash: bool,
+    ) -> Option<Vec<u8>> {
+        let sep = self.separator;
+        let canonical = match self.decoding {
+            Some(_) => canonicalize(path, sep),
+            None => Cow::Borrowed(path),
         };
-        let child = unsafe { self.static_children.get_mut_or_insert_unchecked(p) };
-        child.insert(SegmentsIter::new(route), val)
+        let mut out = Vec::with_capacity(canonical.len() + 1);
+        // Prefer the path without slash fixing.
+        let root = &self.root;
+        let found = root.find_case_insensitive(&canonical, sep, &mut out, false)
+            || (fix_trailing_slash && root.find_case_insensitive(&canonical, sep, &mut out, true));
+        if !found {
+            return None;
+        }
+        if let Cow::Borrowed(_) = canonical {
+            return Some(out);
+        }
+        // `out` is the canonical path with the case of static parts and the trailing separator
+        // fixed. Take the other bytes from the requested path, so escapes stay as sent.
+        let mut raw = Vec::with_capacity(path.len() + 1);
+        let mut rest = path;
+        for (idx, &b) in out.iter().enumerate() {
+            match canonical.get(idx) {
+                Some(&c) if !rest.is_empty() => {
+                    let len = raw_len(rest, sep);
+                    match b == c {
+                        true => raw.extend_from_slice(&rest[..len]),
+                        false => raw.push(b),
+                    }
+                    rest = &rest[len..];
+                }
+                _ => raw.push(b),
+            }
+        }
+        Some(raw)
+    }
+
+    /// Remove the route and return its value. Its id may be given out again if no route
+    /// inserted later is left.
+    /// Params with different names at the same position are treated as the same route, just
+    /// like `insert` does.
+    pub fn remove(&mut self, route: &[u8]) -> Option<T> {
+        let decoding = self.decoding.is_some();
+        let endpoint = with_segments(route, self.separator, decoding, |segments| {
+            self.root.remove(&[], segments)
+        })
+        .ok()??;
+        let entry = self.entries[endpoint.id.0].take();
+        while let Some(None) = self.entries.last() {
+            self.entries.pop();
+        }
+        entry.map(|entry| entry.value)
     }
 }
 
-#[inline(always)]
-pub(crate) fn next_param(path: &[u8]) -> (&[u8], &[u8]) {
-    if let Some(idx) = memchr::memchr(b'/', path) {
-        return unsafe { path.split_at_unchecked(idx) };
+/// Map params borrowed from the canonical form of `raw` back to `raw`.
+fn remap_params<'n, 'p>(
+    params: &[(&'n [u8], &[u8])],
+    canonical: &[u8],
+    raw: &'p [u8],
+    sep: u8,
+) -> Params<'n, 'p> {
+    params
+        .iter()
+        .map(|&(name, value)| {
+            let start = value.as_ptr() as usize - canonical.as_ptr() as usize;
+            let end = raw_offset(raw, start + value.len(), sep);
+            (name, &raw[raw_offset(raw, start, sep)..end])
+        })
+        .collect()
+}
+
+/// Parse the route and call `f` with its segments, consecutive static ones (split by escapes)
+/// merged into one, and canonicalized if `decoding`.
+fn with_segments<R>(
+    route: &[u8],
+    sep: u8,
+    decoding: bool,
+    f: impl FnOnce(&[Segment]) -> R,
+) -> Result<R, InsertError> {
+    let segments = SegmentsIter::new(route, sep).collect::<Result<SmallVec<Segment>, _>>()?;
+    let adjacent_statics = segments
+        .windows(2)
+        .any(|w| matches!(w, [Segment::Static(_), Segment::Static(_)]));
+    if !decoding && !adjacent_statics {
+        return Ok(f(&segments));
+    }
+
+    let mut statics: SmallVec<Cow<[u8]>> = SmallVec::new();
+    let mut after_static = false;
+    for seg in segments.iter() {
+        let Segment::Static(path) = seg else {
+            after_static = false;
+            continue;
+        };
+        let path = match decoding {
+            true => canonicalize(path, sep),
+            false => Cow::Borrowed(*path),
+        };
+        match statics.last_mut() {
+            Some(last) if after_static => last.to_mut().extend_from_slice(&path),
+            _ => statics.push(path),
+        }
+        after_static = true;
+    }
+    let mut statics = statics.iter();
+    let mut merged: SmallVec<Segment> = SmallVec::new();
+    let mut after_static = false;
+    for seg in segments.iter() {
+        match seg {
+            Segment::Static(_) if after_static => continue,
+            Segment::Static(_) => {
+                let path = unsafe { statics.next().unwrap_unchecked() };
+                merged.push(Segment::Static(path));
+                after_static = true;
+            }
+            seg => {
+                merged.push(*seg);
+                after_static = false;
+            }
+        }
     }
-    (path, &[])
+    Ok(f(&merged))
+}
+
+/// Offset of `part`, which is borrowed from `route`, in `route`.
+#[inline(always)]
+fn offset_in(route: &[u8], part: &[u8]) -> usize {
+    part.as_ptr() as usize - route.as_ptr() as usize
+}
+
+/// Whether the value is valid for `:name+`, which must have a non-empty segment.
+#[inline(always)]
+fn is_one_or_more(value: &[u8], sep: u8) -> bool {
+    value.iter().any(|b| *b != sep)
 }
 
 #[inline(always)]
@@ -589,4 +2006,433 @@ mod tests {
             params!(b"path" => b"users/123")
         );
     }
+
+    #[test]
+    fn remove_static() {
+        let mut tree = Tree::new();
+        tree.insert(b"/a/b/c", 1).unwrap();
+        tree.insert(b"/a/b", 2).unwrap();
+        tree.insert(b"/a/bd", 3).unwrap();
+
+        assert_eq!(tree.remove(b"/a/b"), Some(2));
+        assert_eq!(tree.remove(b"/a/b"), None);
+        assert!(tree.at(b"/a/b").is_none());
+        assert_at!(tree, b"/a/b/c", 1);
+        assert_at!(tree, b"/a/bd", 3);
+
+        // "/a/b" is merged back into its only child.
+        assert_eq!(tree.remove(b"/a/bd"), Some(3));
+        let node = tree.root.static_children.get(b'/').unwrap();
+        assert_eq!(node.matching, b"/a/b/c");
+        assert_eq!(node.static_children.children.len(), 0);
+
+        assert_eq!(tree.remove(b"/a/b/c"), Some(1));
+        assert!(tree.root.static_children.children.is_empty());
+        tree.insert(b"/a/b/c", 4).unwrap();
+        assert_at!(tree, b"/a/b/c", 4);
+    }
+
+    #[test]
+    fn remove_param_and_catch_all() {
+        let mut tree = Tree::new();
+        tree.insert(b"/a/:id", 1).unwrap();
+        tree.insert(b"/a/:id/x", 2).unwrap();
+        tree.insert(b"/a/*any", 3).unwrap();
+        tree.insert(b"/a/*any/z", 4).unwrap();
+
+        assert_eq!(tree.remove(b"/a/:id/y"), None);
+        assert_at!(tree, b"/a/1/2/z", 4, params!(b"any" => b"1/2"));
+        assert_eq!(tree.remove(b"/a/*any/z"), Some(4));
+        assert!(tree
+            .root
+            .static_children
+            .get(b'/')
+            .unwrap()
+            .catch_all_child
+            .as_ref()
+            .unwrap()
+            .child
+            .is_none());
+        // Param names are not part of the route identity.
+        assert_eq!(tree.remove(b"/a/:name/x"), Some(2));
+        assert_at!(tree, b"/a/1/x", 3, params!(b"any" => b"1/x"));
+        assert_at!(tree, b"/a/1", 1, params!(b"id" => b"1"));
+
+        assert_eq!(tree.remove(b"/a/:id"), Some(1));
+        assert!(tree
+            .root
+            .static_children
+            .get(b'/')
+            .unwrap()
+            .param_child
+            .is_none());
+        assert_at!(tree, b"/a/1", 3, params!(b"any" => b"1"));
+
+        assert_eq!(tree.remove(b"/a/*any"), Some(3));
+        assert!(tree.at(b"/a/1").is_none());
+        assert!(tree.root.static_children.children.is_empty());
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn regex_param() {
+        let mut tree = Tree::new();
+        tree.insert(br"/users/:id<\d+>", 1).unwrap();
+        tree.insert(b"/users/:name<[a-z]+>/profile", 2).unwrap();
+        tree.insert(b"/users/:name<[a-z]+>", 3).unwrap();
+        assert!(tree.insert(b"/users/:nick<[a-z]+>", 0).is_err());
+        tree.insert(b"/users/*rest", 4).unwrap();
+        tree.insert(b"/users/:x/posts", 5).unwrap();
+        tree.insert(br"/tags/:tag<(?P<x>[a-z]{2})-\d>", 6).unwrap();
+
+        assert_at!(tree, b"/users/42", 1, params!(b"id" => b"42"));
+        assert_at!(tree, b"/users/abc", 3, params!(b"name" => b"abc"));
+        assert_at!(tree, b"/users/abc/profile", 2, params!(b"name" => b"abc"));
+        assert_at!(
+            tree,
+            b"/users/42/profile",
+            4,
+            params!(b"rest" => b"42/profile")
+        );
+        assert_at!(tree, b"/users/4a", 4, params!(b"rest" => b"4a"));
+        assert_at!(tree, b"/users/42/posts", 5, params!(b"x" => b"42"));
+        assert_at!(tree, b"/tags/ab-1", 6, params!(b"tag" => b"ab-1"));
+        assert!(tree.at(b"/tags/ab-12").is_none());
+
+        assert_eq!(tree.remove(br"/users/:id<\d+>"), Some(1));
+        assert_at!(tree, b"/users/42", 4, params!(b"rest" => b"42"));
+    }
+
+    #[cfg(not(feature = "regex"))]
+    #[test]
+    fn regex_param_disabled() {
+        let mut tree = Tree::new();
+        assert!(tree.insert(br"/users/:id<\d+>", 1).is_err());
+    }
+
+    #[test]
+    fn typed_param() {
+        let mut tree = Tree::new();
+        tree.insert(b"/items/:id<u64>", 1).unwrap();
+        tree.insert(b"/items/:id<uuid>", 2).unwrap();
+        tree.insert(b"/items/:slug<len(1,8)>", 3).unwrap();
+        tree.insert(b"/items/:slug<len(1, 8)>/x", 4).unwrap();
+        assert!(tree.insert(b"/items/:n<len(1, 8)>", 0).is_err());
+        tree.insert(b"/items/:hash<hex>", 5).unwrap();
+        tree.insert(b"/items/*rest", 6).unwrap();
+        tree.insert(b"/offsets/:n<i64>", 7).unwrap();
+        tree.insert(b"/names/:n<alnum>", 8).unwrap();
+
+        assert_at!(tree, b"/items/42", 1, params!(b"id" => b"42"));
+        assert_at!(tree, b"/items/18446744073709551615", 1);
+        assert_at!(tree, b"/items/18446744073709551616", 5);
+        assert_at!(
+            tree,
+            b"/items/67e55044-10b1-426f-9247-bb680e5fe0c8",
+            2,
+            params!(b"id" => b"67e55044-10b1-426f-9247-bb680e5fe0c8")
+        );
+        assert_at!(tree, b"/items/a-b", 3, params!(b"slug" => b"a-b"));
+        assert_at!(tree, b"/items/a-b/x", 4, params!(b"slug" => b"a-b"));
+        assert_at!(tree, b"/items/abcdef0123", 5);
+        assert_at!(tree, b"/items/a-b-c-d-e", 6);
+        assert_at!(tree, b"/offsets/-9223372036854775808", 7);
+        assert!(tree.at(b"/offsets/-9223372036854775809").is_none());
+        assert!(tree.at(b"/offsets/+1").is_none());
+        assert_at!(tree, b"/names/abc123", 8);
+        assert!(tree.at(b"/names/abc_123").is_none());
+
+        assert!(tree.insert(b"/bad/:n<len(2,1)>", 0).is_err());
+        assert!(tree.insert(b"/bad/:n<len(a,1)>", 0).is_err());
+    }
+
+    #[test]
+    fn optional() {
+        let mut tree = Tree::new();
+        tree.insert_optional(b"/docs/:version?", 1).unwrap();
+        tree.insert_optional(b"/api(/v1)?/users", 2).unwrap();
+        tree.insert_optional(b"/:lang?/about", 3).unwrap();
+        tree.insert_optional(b"/x(/:a(/:b)?)?", 4).unwrap();
+        tree.insert_optional(b"/sd(here", 5).unwrap();
+
+        assert_at!(tree, b"/docs/1.0", 1, params!(b"version" => b"1.0"));
+        assert_at!(tree, b"/docs", 1);
+        assert_at!(tree, b"/api/v1/users", 2);
+        assert_at!(tree, b"/api/users", 2);
+        assert_at!(tree, b"/en/about", 3, params!(b"lang" => b"en"));
+        assert_at!(tree, b"/about", 3);
+        assert_at!(tree, b"/x/1/2", 4, params!(b"a" => b"1", b"b" => b"2"));
+        assert_at!(tree, b"/x/1", 4, params!(b"a" => b"1"));
+        assert_at!(tree, b"/x", 4);
+        assert_at!(tree, b"/sd(here", 5);
+
+        // Plain insert does not expand optional params.
+        assert!(tree.insert(b"/y/:id?", 0).is_err());
+    }
+
+    #[test]
+    fn optional_conflict() {
+        let mut tree = Tree::new();
+        tree.insert(b"/docs", 1).unwrap();
+        assert!(tree.insert_optional(b"/docs/:version?", 2).is_err());
+        // Nothing is committed.
+        assert!(tree.at(b"/docs/1.0").is_none());
+        // Expanded routes conflicting with each other.
+        assert!(tree.insert_optional(b"/a(/:x)?(/:y)?", 3).is_err());
+        assert!(tree.at(b"/a").is_none());
+        assert!(tree.insert_optional(b"/b/:x<u64>?/:y<", 4).is_err());
+        assert!(tree.at(b"/b").is_none());
+
+        // Errors point into the route as given.
+        let err = tree.insert_optional(b"/docs/:version?", 2).unwrap_err();
+        assert_eq!((err.route(), err.offset()), (&b"/docs/:version?"[..], 0));
+        tree.insert(b"/p/:id/x", 5).unwrap();
+        let err = tree.insert_optional(b"/p(/:name/x)?", 6).unwrap_err();
+        assert_eq!((err.route(), err.offset()), (&b"/p(/:name/x)?"[..], 5));
+        assert!(tree.at(b"/p").is_none());
+
+        // The routes inserted before the conflict are removed again, along with their ids.
+        let id = tree.insert_id(b"/c", 7).unwrap();
+        assert!(tree.insert_optional(b"/docs(/:v)?", 8).is_err());
+        assert!(tree.at(b"/docs/1.0").is_none());
+        assert_eq!(tree.iter().count(), 3);
+        assert_eq!(tree.insert_id(b"/d", 9).unwrap().index(), id.index() + 1);
+        // So are the ids of routes removed last.
+        tree.remove(b"/d");
+        tree.remove(b"/c");
+        assert_eq!(tree.entries.len(), id.index());
+        assert_eq!(tree.insert_id(b"/e", 10).unwrap(), id);
+
+        // Each optional part doubles the routes, which is capped.
+        let mut tree = Tree::new();
+        tree.insert_optional(b"/x(/a)?(/b)?(/c)?(/d)?(/e)?(/f)?(/g)?(/h)?", 1)
+            .unwrap();
+        assert_eq!(tree.iter().count(), 256);
+        let route = b"/y(/a)?(/b)?(/c)?(/d)?(/e)?(/f)?(/g)?(/h)?/:i?";
+        let err = tree.insert_optional(route, 2).unwrap_err();
+        assert_eq!(
+            (err.kind(), err.offset()),
+            (&InsertErrorKind::TooManyOptionals, 43)
+        );
+        let err = tree.insert_optional(&b"/:x?".repeat(64), 3).unwrap_err();
+        assert_eq!(err.kind(), &InsertErrorKind::TooManyOptionals);
+    }
+
+    #[test]
+    fn case_insensitive_path() {
+        let mut tree = Tree::new();
+        tree.insert(b"/users/:id", 1).unwrap();
+        tree.insert(b"/users/:id/Posts", 2).unwrap();
+        tree.insert(b"/Users/me", 3).unwrap();
+        tree.insert(b"/files/*path", 4).unwrap();
+        tree.insert(b"/docs/", 5).unwrap();
+        tree.insert(b"/item/:n<u64>/", 6).unwrap();
+
+        macro_rules! assert_ci {
+            ($path:expr, $fix:expr, $expected:expr) => {
+                assert_eq!(
+                    tree.find_case_insensitive_path($path, $fix).as_deref(),
+                    Some($expected.as_slice())
+                );
+            };
+            ($path:expr, $fix:expr) => {
+                assert!(tree.find_case_insensitive_path($path, $fix).is_none());
+            };
+        }
+        assert_ci!(b"/USERS/Ab", false, b"/users/Ab");
+        assert_ci!(b"/USERS/Ab/posts", false, b"/users/Ab/Posts");
+        assert_ci!(b"/users/ME", false, b"/users/ME");
+        assert_ci!(b"/USERS/mE", false, b"/Users/me");
+        assert_ci!(b"/Files/A/b", false, b"/files/A/b");
+        assert_ci!(b"/Docs", false);
+        assert_ci!(b"/Docs", true, b"/docs/");
+        assert_ci!(b"/USERS/Ab/", true, b"/users/Ab");
+        assert_ci!(b"/ITEM/12", true, b"/item/12/");
+        assert_ci!(b"/ITEM/ab/", true);
+        assert_ci!(b"/nothing", true);
+    }
+
+    #[test]
+    fn at_clean() {
+        let mut tree = Tree::new();
+        tree.insert(b"/a/b/d", 1).unwrap();
+        tree.insert(b"/users/:id", 2).unwrap();
+
+        let mut buf = Vec::new();
+        let (val, _, redirect) = tree.at_clean(b"/a/b/d", &mut buf).unwrap();
+        assert_eq!((*val, redirect), (1, false));
+        let (val, _, redirect) = tree.at_clean(b"/a//b/./c/../d", &mut buf).unwrap();
+        assert_eq!((*val, redirect), (1, true));
+        assert_eq!(buf, b"/a/b/d");
+
+        let mut buf = Vec::new();
+        let (val, params, redirect) = tree.at_clean(b"/../users//42", &mut buf).unwrap();
+        assert_eq!((*val, redirect), (2, true));
+        assert_eq!(params[0].1, b"42");
+        assert!(tree.at_clean(b"/a/b/../c", &mut Vec::new()).is_none());
+    }
+
+    #[test]
+    fn percent_decoding() {
+        let mut tree = Tree::new().with_percent_decoding(EncodedSlash::Reject);
+        tree.insert(b"/my%20doc", 1).unwrap();
+        tree.insert(b"/files/*path", 2).unwrap();
+        tree.insert(b"/users/:name", 3).unwrap();
+        assert!(tree.insert(b"/my doc", 4).is_err());
+        assert_eq!(tree.remove(b"/my doc"), Some(1));
+        tree.insert(b"/my doc", 1).unwrap();
+
+        for path in [&b"/my doc"[..], b"/my%20doc", b"/%6Dy%20doc"] {
+            assert_eq!(*tree.at_decoded(path, &mut Vec::new()).unwrap().0, 1);
+        }
+        // an encoded slash does not split segments
+        assert!(tree.at_decoded(b"/users/a%2Fb", &mut Vec::new()).is_none());
+        assert!(tree.at_decoded(b"/files/a%2fb", &mut Vec::new()).is_none());
+
+        let mut buf = Vec::new();
+        let (val, params) = tree.at_decoded(b"/users/john%20doe", &mut buf).unwrap();
+        assert_eq!((*val, &*params[0].1), (3, &b"john doe"[..]));
+        let mut buf = Vec::new();
+        let (_, params) = tree.at_decoded(b"/users/plain", &mut buf).unwrap();
+        assert!(matches!(params[0].1, Cow::Borrowed(b"plain")));
+        let mut buf = Vec::new();
+        let (_, params) = tree.at_decoded(b"/users/100%25", &mut buf).unwrap();
+        assert_eq!(&*params[0].1, b"100%");
+
+        // Every lookup matches the canonical path, params are sliced from the requested one.
+        assert_at!(tree, b"/my%20doc", 1);
+        assert_at!(
+            tree,
+            b"/%75sers/%6Aohn%20doe",
+            3,
+            params!(b"name" => b"%6Aohn%20doe")
+        );
+        assert_eq!(tree.at_match(b"/my%20doc").unwrap().route, b"/my doc");
+        assert_eq!(tree.matches(b"/files/%61%2fb", &mut Vec::new()).count(), 1);
+        assert_eq!(tree.at_with(b"/my%20doc", |_, _| true).unwrap().0, &1);
+        assert!(matches!(
+            tree.lookup(b"/my%20doc/"),
+            Lookup::TrailingSlashRedirect
+        ));
+        let (val, _, cleaned) = tree.at_clean(b"//my%20doc", &mut Vec::new()).unwrap();
+        assert_eq!((*val, cleaned), (1, true));
+        // Escaped dot segments can not get past cleaning.
+        let mut buf = Vec::new();
+        let (val, params, cleaned) = tree.at_clean(b"/files/%2e%2E/my%20doc", &mut buf).unwrap();
+        assert_eq!((*val, params.len(), cleaned), (1, 0, true));
+        drop(params);
+        assert_eq!(buf, b"/my%20doc");
+        let mut buf = Vec::new();
+        let (_, params, cleaned) = tree.at_clean(b"/files/%2e/a", &mut buf).unwrap();
+        assert_eq!((params[0].1, cleaned), (&b"a"[..], true));
+        // The fixed path keeps the escapes as sent.
+        assert_eq!(
+            tree.find_case_insensitive_path(b"/MY%20DOC", false),
+            Some(b"/my%20doc".to_vec())
+        );
+        assert_eq!(
+            tree.find_case_insensitive_path(b"/%55SERS/%6Aohn%20doe/", true),
+            Some(b"/users/%6Aohn%20doe".to_vec())
+        );
+
+        let mut tree = Tree::new().with_percent_decoding(EncodedSlash::Keep);
+        tree.insert(b"/users/:name", 1).unwrap();
+        let mut buf = Vec::new();
+        let (_, params) = tree.at_decoded(b"/users/a%2fb%20", &mut buf).unwrap();
+        assert_eq!(&*params[0].1, b"a%2Fb ");
+
+        let mut tree = Tree::new().with_percent_decoding(EncodedSlash::Decode);
+        tree.insert(b"/files/*path", 1).unwrap();
+        let mut buf = Vec::new();
+        let (_, params) = tree.at_decoded(b"/files/a%2Fb/c", &mut buf).unwrap();
+        assert_eq!(&*params[0].1, b"a/b/c");
+    }
+
+    #[test]
+    #[should_panic]
+    fn percent_decoding_after_insert() {
+        let mut tree = Tree::new();
+        tree.insert(b"/my%20doc", 1).unwrap();
+        let _ = tree.with_percent_decoding(EncodedSlash::Reject);
+    }
+
+    #[test]
+    fn separator_percent_decoding() {
+        let mut tree = Tree::new()
+            .with_separator(b'.')
+            .with_percent_decoding(EncodedSlash::Reject);
+        tree.insert(b"m.:svc.lat", 1).unwrap();
+        tree.insert(b"m.a%2Eb", 2).unwrap();
+        // An escaped separator does not split segments, an escaped `/` is any other byte.
+        assert_at!(tree, b"m.a%2Eb.lat", 1, params!(b"svc" => b"a%2Eb"));
+        assert_at!(tree, b"m.a%2eb", 2);
+        assert!(tree.at(b"m.a.b").is_none());
+        assert_at!(tree, b"m.a%2Fb.lat", 1, params!(b"svc" => b"a%2Fb"));
+        assert!(tree.at_decoded(b"m.a%2Eb.lat", &mut Vec::new()).is_none());
+        let mut buf = Vec::new();
+        let (_, params) = tree.at_decoded(b"m.a%2Fb.lat", &mut buf).unwrap();
+        assert_eq!(&*params[0].1, b"a/b");
+
+        let mut tree = Tree::new()
+            .with_separator(b'.')
+            .with_percent_decoding(EncodedSlash::Decode);
+        tree.insert(b"m.:svc.lat", 1).unwrap();
+        let mut buf = Vec::new();
+        let (_, params) = tree.at_decoded(b"m.a%2eb.lat", &mut buf).unwrap();
+        assert_eq!(&*params[0].1, b"a.b");
+    }
+
+    #[test]
+    #[should_panic]
+    fn separator_route_syntax() {
+        let _ = Tree::<()>::new().with_separator(b':');
+    }
+
+    #[test]
+    fn separator() {
+        let mut tree = Tree::new().with_separator(b'.');
+        tree.insert(b"metrics.:service.latency", 1).unwrap();
+        tree.insert(b"metrics.:service.", 2).unwrap();
+        tree.insert(b"logs.*path.tail", 3).unwrap();
+        tree.insert(b"events.:kind+", 4).unwrap();
+        tree.insert(b"a/b.:x", 5).unwrap();
+        assert!(tree.insert(b"logs.*path/tail", 0).is_err());
+
+        assert_at!(
+            tree,
+            b"metrics.api.latency",
+            1,
+            params!(b"service" => b"api")
+        );
+        assert_at!(tree, b"logs.a.b.tail", 3, params!(b"path" => b"a.b"));
+        assert_at!(tree, b"events.a.b", 4, params!(b"kind" => b"a.b"));
+        assert_at!(tree, b"a/b.c/d", 5, params!(b"x" => b"c/d"));
+        assert!(tree.at(b"metrics.a.b.latency").is_none());
+        assert!(tree.at(b"events..").is_none());
+
+        assert!(matches!(
+            tree.lookup(b"metrics.api"),
+            Lookup::TrailingSlashRedirect
+        ));
+        let (val, _, cleaned) = tree
+            .at_clean(b"metrics..api.latency", &mut Vec::new())
+            .unwrap();
+        assert_eq!((*val, cleaned), (1, true));
+        assert_eq!(
+            tree.find_case_insensitive_path(b"METRICS.api", true),
+            Some(b"metrics.api.".to_vec())
+        );
+
+        let mut tree = Tree::new().with_separator(b'.');
+        tree.insert_optional(b"docs.:version?", 1).unwrap();
+        assert_eq!(tree.at(b"docs").unwrap().0, &1);
+        assert_at!(tree, b"docs.v1", 1, params!(b"version" => b"v1"));
+        tree.insert_optional(b"logs.*path.:n?", 2).unwrap();
+        assert_at!(
+            tree,
+            b"logs.a.b.c",
+            2,
+            params!(b"path" => b"a.b", b"n" => b"c")
+        );
+    }
 }
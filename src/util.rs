@@ -1,4 +1,6 @@
-use crate::{Params, ParamsOwned, ParamsStr, ParamsStrOwned};
+use std::borrow::Cow;
+
+use crate::{decode::canonicalize, Params, ParamsOwned, ParamsStr, ParamsStrOwned};
 
 pub trait ParamsConvertStr<'k, 'v> {
     /// # Safety
@@ -315,3 +317,140 @@ impl ParamsConvertOwned for ParamsStrOwned {
         self
     }
 }
+
+/// Clean the path to its canonical form:
+/// - Make sure it starts with `/`.
+/// - Collapse duplicate slashes.
+/// - Remove `.` segments.
+/// - Remove `..` segments with the segment before them, `..` at the root is dropped.
+///
+/// A trailing slash is kept. Nothing is allocated if the path is already clean.
+#[inline]
+pub fn clean_path(path: &[u8]) -> Cow<'_, [u8]> {
+    clean_path_by(path, b'/', false)
+}
+
+/// Like `clean_path`, but with segments split by `sep`.
+/// Paths are only made to start with `sep` if it is `/`.
+/// With `decoding`, segments like `%2e%2E` are dot segments too, as they are matched decoded.
+pub(crate) fn clean_path_by(path: &[u8], sep: u8, decoding: bool) -> Cow<'_, [u8]> {
+    let rooted = sep == b'/' || path.first() == Some(&sep);
+    let dots = |segment: &[u8]| match segment {
+        b"." => Some(1),
+        b".." => Some(2),
+        _ if decoding && segment.len() <= 6 && segment.contains(&b'%') => {
+            match &*canonicalize(segment, sep) {
+                b"." => Some(1),
+                b".." => Some(2),
+                _ => None,
+            }
+        }
+        _ => None,
+    };
+    if is_clean(path, sep, rooted, dots) {
+        return Cow::Borrowed(path);
+    }
+
+    let mut out = Vec::with_capacity(path.len() + 1);
+    if rooted {
+        out.push(sep);
+    }
+    let root = out.len();
+    let mut trailing = false;
+    for segment in path.split(|b| *b == sep) {
+        trailing = true;
+        match (segment, dots(segment)) {
+            (b"", _) | (_, Some(1)) => (),
+            (_, Some(_)) => {
+                // drop the last segment, but keep the root slash
+                if out.len() > root {
+                    out.pop();
+                    let idx = memchr::memrchr(sep, &out).map_or(0, |idx| idx + 1);
+                    out.truncate(idx);
+                }
+            }
+            _ => {
+                out.extend_from_slice(segment);
+                out.push(sep);
+                trailing = false;
+            }
+        }
+    }
+    if !trailing && out.len() > root {
+        out.pop();
+    }
+    Cow::Owned(out)
+}
+
+#[inline]
+fn is_clean(path: &[u8], sep: u8, rooted: bool, dots: impl Fn(&[u8]) -> Option<u8>) -> bool {
+    (!rooted || path.first() == Some(&sep))
+        && memchr::memmem::find(path, &[sep, sep]).is_none()
+        && path
+            .split(|b| *b == sep)
+            .all(|segment| dots(segment).is_none())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clean() {
+        let cases: &[(&[u8], &[u8])] = &[
+            // already clean
+            (b"/", b"/"),
+            (b"/abc", b"/abc"),
+            (b"/a/b/c", b"/a/b/c"),
+            (b"/abc/", b"/abc/"),
+            (b"/a/b/c/", b"/a/b/c/"),
+            (b"/a/..b/c.", b"/a/..b/c."),
+            // missing root
+            (b"", b"/"),
+            (b"a/", b"/a/"),
+            (b"abc", b"/abc"),
+            (b"abc/def", b"/abc/def"),
+            // duplicate slashes
+            (b"//", b"/"),
+            (b"/abc//", b"/abc/"),
+            (b"/abc/def//", b"/abc/def/"),
+            (b"/a/b/c//", b"/a/b/c/"),
+            (b"/abc//def//ghi", b"/abc/def/ghi"),
+            (b"//abc", b"/abc"),
+            (b"///abc", b"/abc"),
+            (b"//abc//", b"/abc/"),
+            // . segments
+            (b".", b"/"),
+            (b"./", b"/"),
+            (b"/abc/./def", b"/abc/def"),
+            (b"/./abc/def", b"/abc/def"),
+            (b"/abc/.", b"/abc/"),
+            // .. segments
+            (b"..", b"/"),
+            (b"../", b"/"),
+            (b"../../", b"/"),
+            (b"../..", b"/"),
+            (b"../../abc", b"/abc"),
+            (b"/abc/def/ghi/../jkl", b"/abc/def/jkl"),
+            (b"/abc/def/../ghi/../jkl", b"/abc/jkl"),
+            (b"/abc/def/..", b"/abc/"),
+            (b"/abc/def/../..", b"/"),
+            (b"/abc/def/../../..", b"/"),
+            (b"/abc/def/../../../ghi/jkl/../../../mno", b"/mno"),
+            // combinations
+            (b"abc/./../def", b"/def"),
+            (b"abc//./../def", b"/def"),
+            (b"abc/../../././../def", b"/def"),
+        ];
+        for (path, expected) in cases {
+            let cleaned = clean_path(path);
+            assert_eq!(
+                cleaned.as_ref(),
+                *expected,
+                "{}",
+                String::from_utf8_lossy(path)
+            );
+            assert_eq!(matches!(cleaned, Cow::Borrowed(_)), path == expected);
+        }
+    }
+}
@@ -0,0 +1,117 @@
+use monoio_route::{HostRouter, InsertErrorKind, Tree};
+
+macro_rules! assert_host {
+    ($router:expr, $host:expr, $val:expr, $params:expr) => {
+        let mut buf = Vec::new();
+        let (val, params) = $router.at($host, &mut buf).unwrap();
+        assert_eq!(*val, $val);
+        let expected: &[(&[u8], &[u8])] = $params;
+        assert_eq!(params.as_slice(), expected);
+    };
+}
+
+#[test]
+fn host_router() {
+    let mut router = HostRouter::new();
+    router.insert(b"example.com", 1).unwrap();
+    router.insert(b"API.example.com", 2).unwrap();
+    router.insert(b":tenant.example.com", 3).unwrap();
+    router.insert(b"*.example.com", 4).unwrap();
+    router.insert(b":app-:env.apps.io", 5).unwrap();
+    router.insert(b"*sub.static.io", 6).unwrap();
+    // `:` is escaped in IPv6 literals.
+    router.insert(br"[\:\:1]", 7).unwrap();
+    router.insert(b"b\xc3\xbccher.de", 8).unwrap();
+    router.insert(b"xn--mnchen-3ya.de", 9).unwrap();
+    router.insert(br"\:\:1", 10).unwrap();
+    assert!(router.insert(b":name.example.com", 0).is_err());
+    router.insert(b"trailing.dot.", 11).unwrap();
+    router.insert(b"with.port:8080", 12).unwrap();
+    router.insert(br"[\:\:2]:8080", 13).unwrap();
+    assert!(router.insert(b"with.port", 0).is_err());
+
+    assert_host!(router, b"example.com", 1, &[]);
+    assert_host!(router, b"Example.COM:8080", 1, &[]);
+    assert_host!(router, b"example.com.", 1, &[]);
+    assert_host!(router, b"api.example.com", 2, &[]);
+    assert_host!(router, b"Acme.example.com:443", 3, &[(b"tenant", b"acme")]);
+    assert_host!(router, b"a.b.example.com", 4, &[(b"**", b"a.b")]);
+    assert_host!(
+        router,
+        b"shop-prod.apps.io",
+        5,
+        &[(b"app", b"shop"), (b"env", b"prod")]
+    );
+    assert_host!(router, b"x.y.z.static.io", 6, &[(b"sub", b"x.y.z")]);
+    assert_host!(router, b"[::1]:8080", 7, &[]);
+    assert_host!(router, "BÜCHER.de".as_bytes(), 8, &[]);
+    assert_host!(router, b"xn--bcher-kva.de", 8, &[]);
+    assert_host!(router, b"XN--BCHER-KVA.de:443", 8, &[]);
+    assert_host!(router, "münchen.de".as_bytes(), 9, &[]);
+    assert_host!(router, b"::1", 10, &[]);
+    assert_host!(router, b"trailing.dot", 11, &[]);
+    assert_host!(router, b"trailing.dot.", 11, &[]);
+    assert_host!(router, b"with.port", 12, &[]);
+    assert_host!(router, b"with.port:443", 12, &[]);
+    assert_host!(router, b"[::2]", 13, &[]);
+
+    let mut buf = Vec::new();
+    assert!(router.at(b"static.io", &mut buf).is_none());
+    assert!(router.at(b"example.org", &mut buf).is_none());
+    assert!(router.at(b"a/b.example.com", &mut buf).is_none());
+    assert!(router.at(b".example.com", &mut buf).is_none());
+    assert!(router.at(b"a..example.com", &mut buf).is_none());
+    assert!(router.at(b"xn--.de", &mut buf).is_none());
+}
+
+#[test]
+fn host_with_port() {
+    let mut router = HostRouter::new().with_port_stripping(false);
+    router.insert(br"example.com\:8080", 1).unwrap();
+    router.insert(b"example.com", 2).unwrap();
+
+    assert_host!(router, b"example.com:8080", 1, &[]);
+    assert_host!(router, b"example.com", 2, &[]);
+    assert!(router.at(b"example.com:443", &mut Vec::new()).is_none());
+}
+
+#[test]
+fn host_insert_error() {
+    let mut router = HostRouter::new();
+    router.insert(b":tenant.example.com", ()).unwrap();
+    let err = router.insert(b":name.example.com", ()).unwrap_err();
+    assert_eq!(err.route(), b":name.example.com");
+    assert_eq!(err.offset(), 1);
+    let err = router.insert(b"a.:.com.", ()).unwrap_err();
+    assert_eq!(err.kind(), &InsertErrorKind::EmptyParamName);
+    assert_eq!(err.offset(), 2);
+}
+
+#[test]
+fn host_with_path() {
+    let mut tenant = Tree::new();
+    tenant.insert(b"/users/:id", 1).unwrap();
+    let mut admin = Tree::new();
+    admin.insert(b"/users/:id", 2).unwrap();
+
+    let mut router = HostRouter::new();
+    router.insert(b":tenant.example.com", tenant).unwrap();
+    router.insert(b"admin.example.com", admin).unwrap();
+
+    let mut buf = Vec::new();
+    let (val, params) = router
+        .at_path(b"acme.example.com", b"/users/42", &mut buf)
+        .unwrap();
+    assert_eq!(*val, 1);
+    assert_eq!(
+        params.as_slice(),
+        &[(&b"tenant"[..], &b"acme"[..]), (b"id", b"42")]
+    );
+    let (val, _) = router
+        .at_path(b"admin.example.com", b"/users/42", &mut Vec::new())
+        .unwrap();
+    assert_eq!(*val, 2);
+    assert!(router
+        .at_path(b"acme.example.com", b"/posts", &mut Vec::new())
+        .is_none());
+}
@@ -56,9 +56,16 @@ fn invalid_catchall() {
     make_tree!();
     ok!(b"/non-leading-*catchall");
     ok!(b"/foo/bar*catchall");
-    err!(b"/src/*filepath/x");
+    ok!(b"/src/*filepath/x");
     ok!(b"/src2/");
-    err!(b"/src2/*filepath/x");
+    ok!(b"/src2/*filepath/x");
+    err!(b"/src2/*other/x");
+    err!(b"/src3/*filepath.x");
+    err!(b"/src3/*filepath/*rest");
+    err!(b"/src3/*filepath/:name/*rest");
+    err!(b"/src3/:name*filepath");
+    ok!(b"/assets/**/index.html");
+    err!(b"/assets/***");
 }
 
 #[test]
@@ -188,4 +195,195 @@ fn bare_catchall() {
     make_tree!();
     ok!(b"/*foo");
     ok!(b"foo/*bar");
+    ok!(b"*foo");
+    err!(b"*bar");
+    ok!(b":id");
+    ok!(b":id/x");
+    err!(b":name");
+    err!(b"");
+}
+
+#[test]
+fn invalid_regex_param() {
+    make_tree!();
+    err!(b"/:id<");
+    err!(b"/:id<>");
+    err!(b"/:id<[0-9]+");
+    err!(b"/:<[0-9]+>");
+    err!(b"/:id<[0-9]+>?");
+    err!(b"/:id<[0-9]+>:name");
+    #[cfg(feature = "regex")]
+    {
+        err!(b"/x/:id<[0-9>");
+        ok!(b"/x/:id<[0-9]+>");
+        err!(b"/x/:name<[0-9]+>");
+        ok!(b"/x/:id<[a-z]+>/:name<[0-9]+>");
+        ok!(b"/y/:id<[0-9]+>.json");
+    }
+}
+
+#[test]
+fn params_in_segment() {
+    make_tree!();
+    ok!(b"/files/:name.:ext");
+    ok!(b"/files/:name");
+    ok!(b"/v:major-beta/");
+    ok!(b"/user/:name@:host");
+    err!(b"/files/:stem.:suffix");
+    err!(b"/a/:x:y");
+    err!(b"/a/:x<u64>:y");
+    err!(b"/a/:x*rest");
+}
+
+#[test]
+fn one_or_more() {
+    make_tree!();
+    ok!(b"/static/:path+");
+    ok!(b"/static/*path");
+    ok!(b"/static/:name");
+    err!(b"/static/:other+");
+    ok!(b"/repos/:path+/blob");
+    err!(b"/repos/:path+x");
+    err!(b"/repos/:path+/:sha+");
+    err!(b"/repos/:path+/*rest");
+    err!(b"/:+");
+}
+
+#[test]
+fn error_kind() {
+    use monoio_route::InsertErrorKind;
+
+    let mut tree = monoio_route::Tree::new();
+    tree.insert(b"/users/:id", ()).unwrap();
+    macro_rules! assert_err {
+        ($path:expr, $kind:expr, $offset:expr) => {
+            let err = tree.insert($path, ()).unwrap_err();
+            assert_eq!((err.kind(), err.offset()), (&$kind, $offset));
+            assert_eq!(err.route(), $path);
+        };
+    }
+
+    assert_err!(
+        b"/users/:name",
+        InsertErrorKind::Conflict {
+            with: b"/users/:id".to_vec()
+        },
+        8
+    );
+    assert_err!(b"/a/:/b", InsertErrorKind::EmptyParamName, 3);
+    assert_err!(b"/a/:b?", InsertErrorKind::IllegalParamName, 5);
+    assert_err!("/u/:名".as_bytes(), InsertErrorKind::IllegalParamName, 4);
+    assert_err!(b"/a/*-b", InsertErrorKind::IllegalParamName, 4);
+    assert_err!(b"/a/*rest.txt", InsertErrorKind::CatchAllNotAtEnd, 8);
+    assert_err!(b"/a/:b:c", InsertErrorKind::AdjacentParams, 5);
+    assert_err!(b"/a/:b/:b", InsertErrorKind::DuplicateParamName, 7);
+    assert_err!(b"/a/:b<x", InsertErrorKind::InvalidConstraint, 5);
+    assert_err!(b"/a/*b/*c", InsertErrorKind::MultipleCatchAlls, 7);
+    assert_err!(b"", InsertErrorKind::EmptyRoute, 0);
+
+    let err = tree.insert(b"/users/:name", ()).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "insert route failed: conflicts with route `/users/:id`\n    /users/:name\n            ^"
+    );
+}
+
+#[test]
+fn escaped_static() {
+    let mut tree = monoio_route::Tree::new();
+    assert!(tree.insert(br"/a\:b", ()).is_ok());
+    assert!(tree.insert(br"/a\:b", ()).is_err());
+    assert!(tree.insert(br"/a\*", ()).is_ok());
+    assert!(tree.insert(br"/a\b", ()).is_ok());
+    assert!(tree.insert(br"\:a", ()).is_ok());
+    assert_eq!(tree.remove(br"/a\:b"), Some(()));
+    assert!(tree.insert(br"/a\:b", ()).is_ok());
+}
+
+#[test]
+fn iter_routes() {
+    let routes: &[&[u8]] = &[
+        b"/users/:id",
+        b"/users",
+        b"/",
+        b"/users/:id<u64>/posts",
+        b"/files/*path",
+        b"/about",
+        b"/users/new",
+        br"/a\:b",
+    ];
+    let mut tree = monoio_route::Tree::new();
+    for (i, route) in routes.iter().enumerate() {
+        tree.insert(route, i).unwrap();
+    }
+    tree.remove(b"/about");
+
+    let listed: Vec<_> = tree.iter().map(|(route, val)| (route, *val)).collect();
+    assert_eq!(
+        listed,
+        [
+            (&b"/"[..], 2),
+            (br"/a\:b", 7),
+            (b"/files/*path", 4),
+            (b"/users", 1),
+            (b"/users/new", 6),
+            (b"/users/:id", 0),
+            (b"/users/:id<u64>/posts", 3),
+        ]
+    );
+
+    for (_, val) in tree.iter_mut() {
+        *val *= 10;
+    }
+    for (_, val) in &mut tree {
+        *val += 1;
+    }
+    let values: Vec<_> = (&tree).into_iter().map(|(_, val)| *val).collect();
+    assert_eq!(values, [21, 71, 41, 11, 61, 1, 31]);
+
+    let owned: Vec<_> = tree.into_iter().collect();
+    assert_eq!(owned.len(), 7);
+    assert_eq!(owned[0], (b"/".to_vec(), 21));
+
+    // Constrained params are listed in the same order whatever the insertion order.
+    let routes: &[&[u8]] = &[b"/n/:id<len(1,2)>", b"/n/:id<u64>", b"/n/:id<hex>/x"];
+    let mut forward = monoio_route::Tree::new();
+    let mut backward = monoio_route::Tree::new();
+    for route in routes {
+        forward.insert(route, ()).unwrap();
+    }
+    for route in routes.iter().rev() {
+        backward.insert(route, ()).unwrap();
+    }
+    let forward: Vec<_> = forward.iter().map(|(route, _)| route).collect();
+    let backward: Vec<_> = backward.iter().map(|(route, _)| route).collect();
+    assert_eq!(forward, backward);
+}
+
+#[test]
+fn get_by_route() {
+    let mut tree = monoio_route::Tree::new();
+    tree.insert(b"/users/:id", 1).unwrap();
+    tree.insert(b"/users/new", 2).unwrap();
+    tree.insert(b"/files/*path", 3).unwrap();
+    tree.insert(b"/items/:id<u64>", 4).unwrap();
+
+    assert_eq!(tree.get(b"/users/:id"), Some(&1));
+    // params are the same slot whatever their names
+    assert_eq!(tree.get(b"/users/:name"), Some(&1));
+    assert_eq!(tree.get(b"/users/new"), Some(&2));
+    assert_eq!(tree.get(b"/users/42"), None);
+    assert_eq!(tree.get(b"/files/*rest"), Some(&3));
+    assert_eq!(tree.get(b"/files/:path+"), None);
+    assert_eq!(tree.get(b"/items/:id<u64>"), Some(&4));
+    assert_eq!(tree.get(b"/items/:id"), None);
+    assert_eq!(tree.get(b"/users/:"), None);
+
+    *tree.get_mut(b"/users/:id").unwrap() = 10;
+    assert_eq!(*tree.at(b"/users/42").unwrap().0, 10);
+    assert!(tree.get_mut(b"/posts").is_none());
+
+    assert!(tree.contains_route(b"/files/*path"));
+    assert!(!tree.contains_route(b"/files"));
+    assert!(!tree.contains_route(b"/files/a"));
 }
@@ -1136,3 +1136,447 @@ fn wildcard() {
         ]
     );
 }
+
+#[test]
+fn trailing_slash_redirect() {
+    use monoio_route::Lookup;
+
+    let routes: &[&[u8]] = slices![
+        "/hi",
+        "/b/",
+        "/search/:query",
+        "/cmd/:tool/",
+        "/src/*filepath",
+        "/x",
+        "/x/y",
+        "/y/",
+        "/y/z",
+        "/0/:id",
+        "/0/:id/1",
+        "/1/:id/",
+        "/1/:id/2",
+        "/aa",
+        "/a/",
+        "/admin",
+        "/admin/static",
+        "/admin/:category",
+        "/admin/:category/:page",
+        "/doc",
+        "/doc/rust_faq.html",
+        "/doc/rust1.26.html",
+        "/no/a",
+        "/no/b",
+        "/no/a/b/*other",
+        "/api/:page/:name",
+        "/api/hello/:name/bar/",
+        "/api/bar/:name",
+        "/api/baz/foo",
+        "/api/baz/foo/bar",
+        "/foo/:p",
+    ];
+    let mut tree = monoio_route::Tree::new();
+    for route in routes {
+        tree.insert(route, *route).unwrap();
+    }
+
+    let tsr: &[&[u8]] = slices![
+        "/hi/",
+        "/b",
+        "/search/rustacean/",
+        "/cmd/vet",
+        "/src",
+        "/x/",
+        "/y",
+        "/0/rust/",
+        "/1/rust",
+        "/a",
+        "/admin/",
+        "/doc/",
+        "/admin/static/",
+        "/admin/cfg/",
+        "/admin/cfg/users/",
+        "/api/hello/x/bar",
+        "/api/baz/foo/",
+        "/api/baz/bax/",
+        "/api/bar/huh/",
+        "/api/baz/foo/bar/",
+        "/api/world/abc/",
+        "/foo/pp/",
+        "/no/a/b",
+    ];
+    for path in tsr {
+        assert!(
+            matches!(tree.lookup(path), Lookup::TrailingSlashRedirect),
+            "{}",
+            String::from_utf8_lossy(path)
+        );
+    }
+
+    let not_found: &[&[u8]] = slices![
+        "/",
+        "/no",
+        "/no/",
+        "/_",
+        "/_/",
+        "/api",
+        "/api/",
+        "/api/hello/x/foo",
+        "/api/baz/foo/bad",
+        "/foo/p/p",
+    ];
+    for path in not_found {
+        assert!(
+            matches!(tree.lookup(path), Lookup::NotFound),
+            "{}",
+            String::from_utf8_lossy(path)
+        );
+    }
+
+    match tree.lookup(b"/search/rustacean") {
+        Lookup::Found(val, params) => {
+            assert_eq!(*val, b"/search/:query");
+            assert_eq!(params.as_slice(), *slice_pairs!("query" => "rustacean"));
+        }
+        _ => panic!("expect found"),
+    };
+}
+
+#[test]
+fn params_in_segment() {
+    test_tree!(
+        slices![
+            "/files/:name.:ext",
+            "/files/:name",
+            "/v:major-beta/",
+            "/user/:name@:host/repos",
+            "/id/:id<u64>.json"
+        ],
+        [
+            (
+                "/files/report.pdf",
+                Some("/files/:name.:ext"),
+                slice_pairs!("name" => "report", "ext" => "pdf")
+            ),
+            (
+                "/files/archive.tar.gz",
+                Some("/files/:name.:ext"),
+                slice_pairs!("name" => "archive.tar", "ext" => "gz")
+            ),
+            (
+                "/files/README",
+                Some("/files/:name"),
+                slice_pairs!("name" => "README")
+            ),
+            (
+                "/v2-beta/",
+                Some("/v:major-beta/"),
+                slice_pairs!("major" => "2")
+            ),
+            ("/v2-beta", None, slice_pairs!()),
+            ("/v-beta/", None, slice_pairs!()),
+            (
+                "/user/john@example.com/repos",
+                Some("/user/:name@:host/repos"),
+                slice_pairs!("name" => "john", "host" => "example.com")
+            ),
+            (
+                "/id/42.json",
+                Some("/id/:id<u64>.json"),
+                slice_pairs!("id" => "42")
+            ),
+            ("/id/x.json", None, slice_pairs!()),
+        ]
+    );
+}
+
+#[test]
+fn catch_all_in_middle() {
+    test_tree!(
+        slices![
+            "/repos/*path/blob/:sha",
+            "/repos/*path/tree/:sha",
+            "/repos/*path",
+            "/assets/**/index.html"
+        ],
+        [
+            (
+                "/repos/org/repo/blob/abc",
+                Some("/repos/*path/blob/:sha"),
+                slice_pairs!("path" => "org/repo", "sha" => "abc")
+            ),
+            (
+                "/repos/org/blob/x/blob/abc",
+                Some("/repos/*path/blob/:sha"),
+                slice_pairs!("path" => "org/blob/x", "sha" => "abc")
+            ),
+            (
+                "/repos/org/repo/tree/abc",
+                Some("/repos/*path/tree/:sha"),
+                slice_pairs!("path" => "org/repo", "sha" => "abc")
+            ),
+            (
+                "/repos/org/repo/blob/abc/def",
+                Some("/repos/*path"),
+                slice_pairs!("path" => "org/repo/blob/abc/def")
+            ),
+            (
+                "/repos/org/repo",
+                Some("/repos/*path"),
+                slice_pairs!("path" => "org/repo")
+            ),
+            (
+                "/assets/css/site/index.html",
+                Some("/assets/**/index.html"),
+                slice_pairs!("**" => "css/site")
+            ),
+            ("/assets/index.html", None, slice_pairs!()),
+            ("/assets/css/main.css", None, slice_pairs!()),
+        ]
+    );
+}
+
+#[test]
+fn one_or_more() {
+    test_tree!(
+        slices![
+            "/static/:path+",
+            "/static/",
+            "/files/:path+",
+            "/files/*rest",
+            "/repos/:path+/blob/:sha",
+            "/:id/x"
+        ],
+        [
+            (
+                "/static/css/main.css",
+                Some("/static/:path+"),
+                slice_pairs!("path" => "css/main.css")
+            ),
+            ("/static/", Some("/static/"), slice_pairs!()),
+            ("/static//", None, slice_pairs!()),
+            (
+                "/files/a/b",
+                Some("/files/:path+"),
+                slice_pairs!("path" => "a/b")
+            ),
+            ("/files/", Some("/files/*rest"), slice_pairs!("rest" => "")),
+            (
+                "/files//",
+                Some("/files/*rest"),
+                slice_pairs!("rest" => "/")
+            ),
+            (
+                "/repos/a/b/blob/c",
+                Some("/repos/:path+/blob/:sha"),
+                slice_pairs!("path" => "a/b", "sha" => "c")
+            ),
+            ("/repos//blob/c", None, slice_pairs!()),
+        ]
+    );
+}
+
+#[test]
+fn escaped_static() {
+    test_tree!(
+        slices![
+            r"/v1/:name\:cancel",
+            r"/v1/projects/foo\:bar",
+            r"/glob/\*",
+            r"/glob/\\*rest"
+        ],
+        [
+            (
+                "/v1/jobs:cancel",
+                Some(r"/v1/:name\:cancel"),
+                slice_pairs!("name" => "jobs")
+            ),
+            (
+                "/v1/projects/foo:bar",
+                Some(r"/v1/projects/foo\:bar"),
+                slice_pairs!()
+            ),
+            ("/glob/*", Some(r"/glob/\*"), slice_pairs!()),
+            (
+                r"/glob/\a/b",
+                Some(r"/glob/\\*rest"),
+                slice_pairs!("rest" => "a/b")
+            ),
+            ("/v1/projects/foo", None, slice_pairs!()),
+        ]
+    );
+}
+
+#[test]
+fn non_static_first_segment() {
+    test_tree!(
+        slices![
+            ":tenant/config",
+            ":tenant/users/:id",
+            "admin/config",
+            "*anything"
+        ],
+        [
+            (
+                "acme/config",
+                Some(":tenant/config"),
+                slice_pairs!("tenant" => "acme")
+            ),
+            (
+                "acme/users/42",
+                Some(":tenant/users/:id"),
+                slice_pairs!("tenant" => "acme", "id" => "42")
+            ),
+            ("admin/config", Some("admin/config"), slice_pairs!()),
+            (
+                "admin/users/1",
+                Some(":tenant/users/:id"),
+                slice_pairs!("tenant" => "admin", "id" => "1")
+            ),
+            (
+                "acme/other",
+                Some("*anything"),
+                slice_pairs!("anything" => "acme/other")
+            ),
+            ("", Some("*anything"), slice_pairs!("anything" => "")),
+        ]
+    );
+}
+
+#[test]
+fn all_matches() {
+    let mut tree = monoio_route::Tree::new();
+    let routes: &[&[u8]] = &[
+        b"/users/new",
+        b"/users/:id",
+        b"/users/:id<u64>",
+        b"/users/:rest+",
+        b"/users/*any",
+        b"/users/new/",
+        b"/:section/new",
+    ];
+    for route in routes {
+        tree.insert(route, *route).unwrap();
+    }
+
+    let mut buf = Vec::new();
+    let matched: Vec<_> = tree.matches(b"/users/new", &mut buf).collect();
+    assert_eq!(
+        matched.iter().map(|(route, _)| **route).collect::<Vec<_>>(),
+        [
+            &b"/users/new"[..],
+            b"/users/:id",
+            b"/users/:rest+",
+            b"/users/*any",
+            b"/:section/new"
+        ]
+    );
+    assert_eq!(matched[1].1[0], (&b"id"[..], &b"new"[..]));
+    assert_eq!(matched[4].1[0], (&b"section"[..], &b"users"[..]));
+    // `at` gives the first match.
+    assert_eq!(
+        tree.at(b"/users/42").map(|(route, _)| *route),
+        tree.matches(b"/users/42", &mut Vec::new())
+            .next()
+            .map(|(route, _)| *route)
+    );
+    assert_eq!(
+        tree.matches(b"/users/42", &mut Vec::new())
+            .map(|(route, _)| *route)
+            .collect::<Vec<_>>(),
+        [
+            &b"/users/:id"[..],
+            b"/users/:id<u64>",
+            b"/users/:rest+",
+            b"/users/*any"
+        ]
+    );
+    assert_eq!(
+        tree.matches(b"/users/", &mut Vec::new())
+            .map(|(route, _)| *route)
+            .collect::<Vec<_>>(),
+        [&b"/users/*any"[..]]
+    );
+    assert_eq!(tree.matches(b"/posts", &mut Vec::new()).count(), 0);
+}
+
+#[test]
+fn guarded_routes() {
+    let mut tree = monoio_route::Tree::new();
+    tree.insert(b"/api/users/:id", ("v2", 1)).unwrap();
+    tree.insert(b"/api/users/:id<u64>", ("v1", 2)).unwrap();
+    tree.insert(b"/api/*rest", ("any", 3)).unwrap();
+
+    let version = |wanted: &'static str| {
+        move |(v, _): &(&str, i32), _: &monoio_route::Params| *v == wanted || *v == "any"
+    };
+    let (val, params) = tree.at_with(b"/api/users/42", version("v1")).unwrap();
+    assert_eq!((val.1, params[0].1), (2, &b"42"[..]));
+    let (val, _) = tree.at_with(b"/api/users/42", version("v2")).unwrap();
+    assert_eq!(val.1, 1);
+    let (val, params) = tree.at_with(b"/api/users/42", version("v3")).unwrap();
+    assert_eq!((val.1, params[0].1), (3, &b"users/42"[..]));
+    // the params are those of the accepted route
+    let (val, params) = tree
+        .at_with(b"/api/users/42", |_, params| params[0].0 == b"rest")
+        .unwrap();
+    assert_eq!((val.1, params.len()), (3, 1));
+    assert!(tree.at_with(b"/api/users/42", |_, _| false).is_none());
+}
+
+#[test]
+fn matched_route() {
+    let mut tree = monoio_route::Tree::new();
+    tree.insert(b"/users/:id", 1).unwrap();
+    tree.insert(br"/files/:name\:raw", 2).unwrap();
+    tree.insert_optional(b"/docs/:version?", 3).unwrap();
+
+    let matched = tree.at_match(b"/users/42").unwrap();
+    assert_eq!(
+        (*matched.value, matched.route, matched.params[0].1),
+        (1, &b"/users/:id"[..], &b"42"[..])
+    );
+    assert_eq!(
+        tree.at_match(b"/files/a:raw").unwrap().route,
+        br"/files/:name\:raw"
+    );
+    assert_eq!(tree.at_match(b"/docs").unwrap().route, b"/docs");
+    assert_eq!(tree.at_match(b"/docs/v1").unwrap().route, b"/docs/:version");
+    assert!(tree.at_match(b"/posts").is_none());
+}
+
+#[test]
+fn route_ids() {
+    let mut tree = monoio_route::Tree::new();
+    let users = tree.insert_id(b"/users/:id", 1).unwrap();
+    let files = tree.insert_id(b"/files/*path", 2).unwrap();
+    assert!(tree.insert_id(b"/users/:name", 0).is_err());
+    tree.insert(b"/", 3).unwrap();
+    let posts = tree.insert_id(b"/posts", 4).unwrap();
+    assert_eq!([users.index(), files.index(), posts.index()], [0, 1, 3]);
+
+    let matched = tree.at_match(b"/users/42").unwrap();
+    assert_eq!((matched.id, *matched.value), (users, 1));
+    drop(matched);
+    assert_eq!(tree.get_by_id(files), Some(&2));
+    assert_eq!(tree.route_pattern(files), Some(&b"/files/*path"[..]));
+
+    // ids stay the same after other routes are inserted or removed
+    assert_eq!(tree.remove(b"/files/*path"), Some(2));
+    let other = tree.insert_id(b"/files/*path", 5).unwrap();
+    assert_eq!(other.index(), 4);
+    assert_eq!(tree.get_by_id(files), None);
+    assert_eq!(tree.route_pattern(files), None);
+    assert_eq!(tree.get_by_id(other), Some(&5));
+    assert_eq!(tree.get_by_id(posts), Some(&4));
+    assert_eq!(tree.at_match(b"/posts").unwrap().id, posts);
+
+    *tree.get_by_id_mut(posts).unwrap() = 6;
+    assert_eq!(tree.at(b"/posts").unwrap().0, &6);
+
+    // every expanded route gets its own id
+    let ids = tree.insert_optional_id(b"/docs/:version?", 7).unwrap();
+    assert_eq!(ids.iter().map(|id| id.index()).collect::<Vec<_>>(), [5, 6]);
+    assert_eq!(tree.route_pattern(ids[0]), Some(&b"/docs/:version"[..]));
+    assert_eq!(tree.route_pattern(ids[1]), Some(&b"/docs"[..]));
+    assert_eq!(tree.at_match(b"/docs").unwrap().id, ids[1]);
+}
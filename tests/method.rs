@@ -0,0 +1,112 @@
+use monoio_route::{InsertErrorKind, MethodLookup, MethodRouter};
+
+macro_rules! assert_allowed {
+    ($lookup:expr, $variant:ident, $expected:expr) => {
+        match $lookup {
+            MethodLookup::$variant { allowed } => {
+                assert_eq!(allowed.header_value(), $expected.as_bytes())
+            }
+            other => panic!("unexpected {other:?}"),
+        }
+    };
+}
+
+#[test]
+fn method_router() {
+    let mut router = MethodRouter::new();
+    router.insert(b"POST", b"/users", 1).unwrap();
+    router.insert(b"GET", b"/users", 2).unwrap();
+    router.insert(b"GET", b"/users/:id", 3).unwrap();
+    router.insert(b"PURGE", b"/users/:id", 4).unwrap();
+    router.insert(b"DELETE", b"/users/:id", 5).unwrap();
+    router.insert(b"OPTIONS", b"/custom", 6).unwrap();
+    let err = router.insert(b"GET", b"/users/:name", 0).unwrap_err();
+    assert_eq!(
+        err.kind(),
+        &InsertErrorKind::Conflict {
+            with: b"/users/:id".to_vec()
+        }
+    );
+
+    match router.at(b"GET", b"/users/42") {
+        MethodLookup::Found(val, params) => {
+            assert_eq!(*val, 3);
+            assert_eq!(params[0], (&b"id"[..], &b"42"[..]));
+        }
+        other => panic!("unexpected {other:?}"),
+    }
+    assert!(matches!(
+        router.at(b"PURGE", b"/users/42"),
+        MethodLookup::Found(4, _)
+    ));
+    // HEAD falls back to GET.
+    assert!(matches!(
+        router.at(b"HEAD", b"/users"),
+        MethodLookup::Found(2, _)
+    ));
+    assert!(matches!(
+        router.at(b"OPTIONS", b"/custom"),
+        MethodLookup::Found(6, _)
+    ));
+
+    assert_allowed!(
+        router.at(b"PUT", b"/users"),
+        MethodNotAllowed,
+        "GET, HEAD, POST, OPTIONS"
+    );
+    assert_allowed!(
+        router.at(b"POST", b"/users/42"),
+        MethodNotAllowed,
+        "GET, HEAD, DELETE, OPTIONS, PURGE"
+    );
+    assert_allowed!(
+        router.at(b"OPTIONS", b"/users"),
+        Options,
+        "GET, HEAD, POST, OPTIONS"
+    );
+    assert_allowed!(router.at(b"GET", b"/custom"), MethodNotAllowed, "OPTIONS");
+    assert_eq!(router.at(b"GET", b"/posts"), MethodLookup::NotFound);
+    assert_eq!(router.at(b"OPTIONS", b"/posts"), MethodLookup::NotFound);
+}
+
+#[test]
+fn shared_route() {
+    let mut router = MethodRouter::new();
+    router.insert(b"GET", b"/users/:id", 1).unwrap();
+    router.insert(b"PUT", b"/users/:id", 2).unwrap();
+    router.insert(b"POST", b"/users/new", 3).unwrap();
+    assert!(router.insert(b"PUT", b"/users/:id", 0).is_err());
+    router.insert(b"DELETE", b"/users/:name", 4).unwrap();
+    // A failed insert leaves the route as it was.
+    assert!(matches!(
+        router.at(b"PUT", b"/users/42"),
+        MethodLookup::Found(2, _)
+    ));
+
+    // Each method names the params as it was inserted.
+    match router.at(b"DELETE", b"/users/42") {
+        MethodLookup::Found(val, params) => {
+            assert_eq!(*val, 4);
+            assert_eq!(params[0], (&b"name"[..], &b"42"[..]));
+        }
+        other => panic!("unexpected {other:?}"),
+    }
+
+    // Routes matching the path are tried until one has the method.
+    match router.at(b"GET", b"/users/new") {
+        MethodLookup::Found(val, params) => {
+            assert_eq!(*val, 1);
+            assert_eq!(params[0], (&b"id"[..], &b"new"[..]));
+        }
+        other => panic!("unexpected {other:?}"),
+    }
+    assert!(matches!(
+        router.at(b"POST", b"/users/new"),
+        MethodLookup::Found(3, _)
+    ));
+    assert_allowed!(
+        router.at(b"PATCH", b"/users/new"),
+        MethodNotAllowed,
+        "GET, HEAD, POST, PUT, DELETE, OPTIONS"
+    );
+}
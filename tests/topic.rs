@@ -0,0 +1,63 @@
+use monoio_route::{InsertErrorKind, TopicTree};
+
+macro_rules! assert_matches {
+    ($tree:expr, $topic:expr, $expected:expr) => {
+        let mut matched: Vec<_> = $tree.matches($topic).copied().collect();
+        matched.sort_unstable();
+        let expected: &[u32] = &$expected;
+        assert_eq!(matched, expected, "{}", String::from_utf8_lossy($topic));
+    };
+}
+
+#[test]
+fn topic_tree() {
+    let mut tree = TopicTree::new();
+    tree.insert(b"sport/tennis/player1", 1).unwrap();
+    tree.insert(b"sport/tennis/+", 2).unwrap();
+    tree.insert(b"sport/+/player1", 3).unwrap();
+    tree.insert(b"sport/#", 4).unwrap();
+    tree.insert(b"#", 5).unwrap();
+    tree.insert(b"+/+/+", 6).unwrap();
+    tree.insert(b"sport/tennis/player1", 7).unwrap();
+    tree.insert(b"$SYS/#", 8).unwrap();
+    tree.insert(b"a:b/*", 9).unwrap();
+
+    assert_matches!(tree, b"sport/tennis/player1", [1, 2, 3, 4, 5, 6, 7]);
+    assert_matches!(tree, b"sport/tennis/player2", [2, 4, 5, 6]);
+    assert_matches!(tree, b"sport/golf/player1", [3, 4, 5, 6]);
+    assert_matches!(tree, b"sport", [4, 5]);
+    assert_matches!(tree, b"sport/", [4, 5]);
+    assert_matches!(tree, b"sport/tennis/player1/ranking", [4, 5]);
+    assert_matches!(tree, b"news//x", [5, 6]);
+    assert_matches!(tree, b"$SYS/broker/load", [8]);
+    assert_matches!(tree, b"$SYS", [8]);
+    assert_matches!(tree, b"a:b/*", [5, 9]);
+    assert_matches!(tree, b"a:b/c", [5]);
+
+    let mut tree = TopicTree::new();
+    tree.insert(b"a/+", 1).unwrap();
+    tree.insert(b"+/+", 2).unwrap();
+    tree.insert(b"a/", 3).unwrap();
+    assert_matches!(tree, b"a/", [1, 2, 3]);
+    assert_matches!(tree, b"a/b", [1, 2]);
+    assert_matches!(tree, b"a", []);
+    assert_matches!(tree, b"/", [2]);
+}
+
+#[test]
+fn invalid_filter() {
+    let mut tree = TopicTree::new();
+    for (filter, offset) in [
+        (&b"sport/tennis#"[..], 6),
+        (b"sport/#/ranking", 8),
+        (b"sport+", 0),
+        (b"a/b+/c", 2),
+    ] {
+        let err = tree.insert(filter, ()).unwrap_err();
+        assert_eq!(
+            (err.kind(), err.offset()),
+            (&InsertErrorKind::InvalidWildcard, offset)
+        );
+    }
+    assert_eq!(tree.matches(b"sport/tennis#").count(), 0);
+}